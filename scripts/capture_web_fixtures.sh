@@ -0,0 +1,24 @@
+#!/bin/sh
+# Capture NHL web API responses as test fixtures, e.g. during a game:
+#
+#   scripts/capture_web_fixtures.sh NJD 2024020151 during_01
+#
+# saves the club schedule to data/web_NJD_schedule.json and the game's
+# landing to data/web_NJD_during_01_landing.json. Run it again with a new
+# label at each point of the game worth testing.
+set -eu
+
+if [ $# -ne 3 ]; then
+    echo "usage: $0 <team abbreviation> <game id> <label>" >&2
+    exit 1
+fi
+
+team=$1
+game_id=$2
+label=$3
+base=${NHL_WEB_API_BASE:-https://api-web.nhle.com/v1}
+data=$(dirname "$0")/../data
+
+curl -fsS "$base/club-schedule-season/$team/now" -o "$data/web_${team}_schedule.json"
+curl -fsS "$base/gamecenter/$game_id/landing" -o "$data/web_${team}_${label}_landing.json"
+echo "captured $team schedule and game $game_id landing as $label"
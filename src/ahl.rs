@@ -0,0 +1,309 @@
+//! AHL schedule support, scraped from the team's schedule page.
+
+use crate::{error::EngineError, fetch_text, state::AppState, tickets::Ticket};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::US::Pacific;
+use log::{info, warn};
+use scraper::{ElementRef, Html, Selector};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const BARRACUDA_SCHEDULE_URL: &str = "https://www.sjbarracuda.com/games";
+
+/// The team name used for Barracuda entries in the ticket list.
+pub const BARRACUDA_TEAM: &str = "SJB";
+
+/// The schedule page only changes when games are added or moved, so there is
+/// no need to scrape it on every device wake.
+const SCHEDULE_CACHE_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HomeOrAway {
+    Home,
+    Away,
+}
+
+#[derive(Debug, Clone)]
+pub struct AhlGame {
+    pub date: DateTime<Utc>,
+    pub opponent_name: String,
+    pub home_or_away: HomeOrAway,
+    pub ticket: Option<Ticket>,
+}
+
+impl AhlGame {
+    /// Games from a ticket list are always home games.
+    fn from_ticket(ticket: &Ticket) -> AhlGame {
+        AhlGame {
+            opponent_name: ticket.opponent.clone(),
+            date: ticket.date,
+            home_or_away: HomeOrAway::Home,
+            ticket: Some(ticket.clone()),
+        }
+    }
+
+    pub fn display_name(&self) -> String {
+        match self.home_or_away {
+            HomeOrAway::Home => format!("vs {}", self.opponent_name),
+            HomeOrAway::Away => format!("@ {}", self.opponent_name),
+        }
+    }
+}
+
+pub fn load_games_from_list(tickets: &[Ticket]) -> Vec<AhlGame> {
+    tickets.iter().map(AhlGame::from_ticket).collect()
+}
+
+/// Attach each ticket to the scraped game on the same local day. The ticket
+/// list names opponents loosely ("Icehogs") so the date is what matches.
+pub fn attach_tickets(games: &mut [AhlGame], tickets: &[Ticket]) {
+    for ticket in tickets {
+        let ticket_day = ticket.date.with_timezone(&Pacific).date();
+        match games
+            .iter_mut()
+            .find(|game| game.date.with_timezone(&Pacific).date() == ticket_day)
+        {
+            Some(game) => game.ticket = Some(ticket.clone()),
+            None => warn!(
+                "no scheduled game for ticket vs {} on {}",
+                ticket.opponent, ticket_day
+            ),
+        }
+    }
+}
+
+fn element_text(element: &ElementRef, selector: &Selector) -> Option<String> {
+    element
+        .select(selector)
+        .next()
+        .map(|found| found.text().collect::<String>().trim().to_string())
+}
+
+/// Parse a team schedule page into games. The page groups games into one
+/// block per month whose id carries the year (e.g. `October2022`); each
+/// entry has the weekday, date and local start time, whether the game is
+/// home or away, and the opponent. Entries that can't be read are skipped,
+/// but a page where none can be read is an error.
+pub fn parse_schedule(html: &str) -> Result<Vec<AhlGame>, EngineError> {
+    let month_selector = Selector::parse("div.widget_inner[id]").expect("month selector");
+    let entry_selector = Selector::parse("div.entry").expect("entry selector");
+    let date_selector = Selector::parse(".date-time .date").expect("date selector");
+    let time_selector = Selector::parse(".date-time .time").expect("time selector");
+    let home_or_away_selector = Selector::parse(".home-or-away").expect("home-or-away selector");
+    let team_title_selector = Selector::parse(".team-title").expect("team-title selector");
+
+    let document = Html::parse_document(html);
+    let mut games = vec![];
+    let mut skipped = 0;
+    let mut months = 0;
+    for month in document.select(&month_selector) {
+        months += 1;
+        let month_id = month.value().id().unwrap_or_default();
+        let year = match month_id
+            .trim_start_matches(char::is_alphabetic)
+            .parse::<i32>()
+        {
+            Ok(year) => year,
+            Err(_) => {
+                warn!("skipping AHL month block {}: no year", month_id);
+                skipped += month.select(&entry_selector).count();
+                continue;
+            }
+        };
+        for entry in month.select(&entry_selector) {
+            let fields = (
+                element_text(&entry, &date_selector),
+                element_text(&entry, &time_selector),
+                element_text(&entry, &home_or_away_selector),
+                element_text(&entry, &team_title_selector),
+            );
+            let (date, time, home_or_away, opponent_name) = match fields {
+                (Some(date), Some(time), Some(home_or_away), Some(opponent_name)) => {
+                    (date, time, home_or_away, opponent_name)
+                }
+                _ => {
+                    warn!("skipping AHL game in {}: missing fields", month_id);
+                    skipped += 1;
+                    continue;
+                }
+            };
+
+            let date_time = format!("{} {} {}", date, year, time);
+            let date = match NaiveDateTime::parse_from_str(&date_time, "%a, %b %d %Y %I:%M%p")
+                .ok()
+                .and_then(|naive_date| Pacific.from_local_datetime(&naive_date).earliest())
+            {
+                Some(date) => date.with_timezone(&Utc),
+                None => {
+                    warn!("skipping AHL game on {}: unreadable time", date_time);
+                    skipped += 1;
+                    continue;
+                }
+            };
+
+            games.push(AhlGame {
+                date,
+                opponent_name,
+                home_or_away: if home_or_away.eq_ignore_ascii_case("home") {
+                    HomeOrAway::Home
+                } else {
+                    HomeOrAway::Away
+                },
+                ticket: None,
+            });
+        }
+    }
+    if games.is_empty() && skipped > 0 {
+        return Err(EngineError::data_shape(
+            "schedule page",
+            format!("none of {} games could be read", skipped),
+        ));
+    }
+    // A page with month blocks but no games has changed its markup, not
+    // run out of games.
+    if games.is_empty() && months > 0 {
+        return Err(EngineError::data_shape(
+            "schedule page",
+            format!("no games in {} month blocks", months),
+        ));
+    }
+    games.sort_by_key(|game| game.date);
+    Ok(games)
+}
+
+struct CachedSchedule {
+    fetched_at: Instant,
+    games: Vec<AhlGame>,
+}
+
+#[derive(Default)]
+pub struct ScheduleCache {
+    cached: Mutex<Option<CachedSchedule>>,
+}
+
+/// The Barracuda schedule, scraped at most once per `SCHEDULE_CACHE_TTL`.
+/// Replays skip the cache, since the recording already reflects what it held.
+pub async fn get_barracuda_schedule(state: &AppState) -> Result<Vec<AhlGame>, EngineError> {
+    let cache = &state.barracuda_schedule;
+    if let Some(cached) = cache.cached.lock().expect("schedule cache").as_ref() {
+        if cached.fetched_at.elapsed() < SCHEDULE_CACHE_TTL && !state.replaying() {
+            return Ok(cached.games.clone());
+        }
+    }
+
+    let url = &state.config.upstreams.barracuda;
+    info!("scraping {}", url);
+    let html = fetch_text(state, "barracuda", url).await?;
+    let games = parse_schedule(&html)?;
+    *cache.cached.lock().expect("schedule cache") = Some(CachedSchedule {
+        fetched_at: Instant::now(),
+        games: games.clone(),
+    });
+    Ok(games)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BARRACUDA_TEXT: &str = include_str!("../data/barracuda.html");
+
+    #[test]
+    fn test_parse_schedule() {
+        let games = parse_schedule(BARRACUDA_TEXT).expect("parse_schedule");
+        assert_eq!(games.len(), 72);
+
+        assert_eq!(games[0].opponent_name, "Iowa Wild");
+        assert_eq!(games[0].home_or_away, HomeOrAway::Away);
+        assert_eq!(
+            games[0].date,
+            DateTime::parse_from_rfc3339("2022-10-14T17:00:00-07:00").unwrap()
+        );
+        assert_eq!(games[0].display_name(), "@ Iowa Wild");
+
+        assert_eq!(games[2].opponent_name, "Henderson Silver Knights");
+        assert_eq!(games[2].home_or_away, HomeOrAway::Home);
+        assert_eq!(games[2].display_name(), "vs Henderson Silver Knights");
+
+        let last = games.last().unwrap();
+        assert!(last.date > games[0].date);
+        assert_eq!(
+            last.date
+                .with_timezone(&Pacific)
+                .format("%Y-%m")
+                .to_string(),
+            "2023-04"
+        );
+    }
+
+    #[test]
+    fn test_attach_tickets() {
+        let mut games = parse_schedule(BARRACUDA_TEXT).expect("parse_schedule");
+        let tickets = crate::tickets::parse_tickets(
+            r#"
+            [[tickets]]
+            opponent = "Silver Knights"
+            date = "2022-10-23T01:00:00Z"
+            league = "ahl"
+            team = "SJB"
+            section = "113"
+            "#,
+        )
+        .expect("tickets");
+        attach_tickets(&mut games, &tickets);
+        let ticketed: Vec<&AhlGame> = games.iter().filter(|game| game.ticket.is_some()).collect();
+        assert_eq!(ticketed.len(), 1);
+        assert_eq!(ticketed[0].opponent_name, "Henderson Silver Knights");
+    }
+
+    #[test]
+    fn test_parse_schedule_malformed() {
+        let entry = |date: &str, team: &str| {
+            format!(
+                r#"<div class="entry"><div class="date-time"><span class="date">{}</span>
+                <span class="time">7:00PM</span></div><span class="home-or-away">Home</span>
+                <span class="team-title">{}</span></div>"#,
+                date, team
+            )
+        };
+        let page = format!(
+            r#"<html><body><div class="widget_inner" id="October2022">{}{}{}</div>
+            <div class="widget_inner" id="November">{}</div></body></html>"#,
+            entry("Sat, Oct 22", "Henderson Silver Knights"),
+            entry("Someday", "Iowa Wild"),
+            r#"<div class="entry"><span class="team-title">Abbotsford</span></div>"#,
+            entry("Sat, Nov 05", "Ontario Reign"),
+        );
+        let games = parse_schedule(&page).expect("parse_schedule");
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].opponent_name, "Henderson Silver Knights");
+
+        let truncated = &BARRACUDA_TEXT[..BARRACUDA_TEXT.len() / 2];
+        assert!(!parse_schedule(truncated).unwrap().is_empty());
+
+        let unreadable = format!(
+            r#"<div class="widget_inner" id="October2022">{}</div>"#,
+            entry("Someday", "Iowa Wild")
+        );
+        assert!(matches!(
+            parse_schedule(&unreadable),
+            Err(EngineError::DataShape { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_schedule_empty() {
+        let games = parse_schedule("<html><body></body></html>").expect("parse_schedule");
+        assert!(games.is_empty());
+
+        let renamed = r#"<html><body><div class="widget_inner" id="October2022">
+            <div class="game-row"><span class="team-title">Iowa Wild</span></div>
+            </div></body></html>"#;
+        assert!(matches!(
+            parse_schedule(renamed),
+            Err(EngineError::DataShape { .. })
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+//! In-process cache of upstream NHL responses. How long a response stays
+//! fresh depends on the state of the game it describes: seconds while the
+//! game is live, minutes on game day, hours when nothing is on.
+
+use crate::{
+    config::CacheTtls, error::EngineError, fetch_text, metrics, state::AppState, NextUp,
+    NextUpState,
+};
+use chrono::{DateTime, Utc};
+use log::info;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Defaults for the `[cache]` settings.
+pub const LIVE_TTL: Duration = Duration::from_secs(15);
+pub const GAME_DAY_TTL: Duration = Duration::from_secs(5 * 60);
+pub const FINAL_TTL: Duration = Duration::from_secs(30 * 60);
+pub const OFF_DAY_TTL: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// Games starting within this long count as game day.
+const GAME_DAY_WINDOW: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub endpoint: &'static str,
+    pub team_id: usize,
+}
+
+impl CacheKey {
+    pub fn new(endpoint: &'static str, team_id: usize) -> Self {
+        Self { endpoint, team_id }
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.endpoint, self.team_id)
+    }
+}
+
+struct Entry {
+    expires_at: Instant,
+    body: String,
+}
+
+#[derive(Default)]
+pub struct ResponseCache {
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+}
+
+impl ResponseCache {
+    pub fn get(&self, key: &CacheKey) -> Option<String> {
+        let mut entries = self.entries.lock().expect("response cache");
+        let now = Instant::now();
+        match entries.get(key) {
+            Some(entry) if entry.expires_at > now => {
+                metrics::observe_cache_lookup(key.endpoint, true);
+                info!(
+                    "cache hit {} ({}s left)",
+                    key,
+                    (entry.expires_at - now).as_secs()
+                );
+                Some(entry.body.clone())
+            }
+            Some(_) => {
+                metrics::observe_cache_lookup(key.endpoint, false);
+                info!("cache miss {} (expired)", key);
+                entries.remove(key);
+                None
+            }
+            None => {
+                metrics::observe_cache_lookup(key.endpoint, false);
+                info!("cache miss {}", key);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: CacheKey, body: String, ttl: Duration) {
+        info!("caching {} for {}s", key, ttl.as_secs());
+        self.entries.lock().expect("response cache").insert(
+            key,
+            Entry {
+                expires_at: Instant::now() + ttl,
+                body,
+            },
+        );
+    }
+}
+
+/// How long responses that produced `next` stay fresh. Upcoming games are
+/// never cached past their start time, so the switch to live isn't missed.
+pub fn ttl_for(next: &NextUp, utc_now: &DateTime<Utc>, ttls: &CacheTtls) -> Duration {
+    match next.state {
+        NextUpState::Live => ttls.live(),
+        NextUpState::Final => ttls.final_game(),
+        NextUpState::NoGames | NextUpState::Standings => ttls.off_day(),
+        NextUpState::Upcoming => {
+            let until_start = (next.date - *utc_now).to_std().unwrap_or_default();
+            let ttl = if until_start <= GAME_DAY_WINDOW {
+                ttls.game_day()
+            } else {
+                ttls.off_day()
+            };
+            ttl.min(until_start).max(ttls.live())
+        }
+    }
+}
+
+/// Upstream responses fetched while building one `NextUp`. They are only
+/// cached once the `NextUp` is built, since that is when the game state is
+/// known.
+pub struct CacheFill<'a> {
+    state: &'a AppState,
+    fetched: Vec<(CacheKey, String)>,
+}
+
+impl<'a> CacheFill<'a> {
+    pub fn new(state: &'a AppState) -> Self {
+        Self {
+            state,
+            fetched: vec![],
+        }
+    }
+
+    /// The body at `url`, from the cache if it is still fresh. Replays skip
+    /// the cache, since the recording already reflects what it held.
+    pub async fn fetch(&mut self, key: CacheKey, url: &str) -> Result<String, EngineError> {
+        if self.state.replaying() {
+            return fetch_text(self.state, key.endpoint, url).await;
+        }
+        if let Some(body) = self.state.responses.get(&key) {
+            return Ok(body);
+        }
+        let body = fetch_text(self.state, key.endpoint, url).await?;
+        self.fetched.push((key, body.clone()));
+        Ok(body)
+    }
+
+    pub fn store(self, next: &NextUp, utc_now: &DateTime<Utc>) {
+        let ttl = ttl_for(next, utc_now, &self.state.config.cache);
+        self.store_for(ttl);
+    }
+
+    /// Cache for a fixed time, for responses that don't follow any one game.
+    pub fn store_for(self, ttl: Duration) {
+        for (key, body) in self.fetched {
+            self.state.responses.insert(key, body, ttl);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn next_up(state: NextUpState, date: &str) -> NextUp {
+        NextUp {
+            state,
+            date: at(date),
+            ..NextUp::default()
+        }
+    }
+
+    #[test]
+    fn test_ttl_for() {
+        let now = at("2021-03-21T12:00:00Z");
+        let ttls = CacheTtls::default();
+        let ttl = |state, date| ttl_for(&next_up(state, date), &now, &ttls);
+        assert_eq!(ttl(NextUpState::Live, "2021-03-21T11:00:00Z"), LIVE_TTL);
+        assert_eq!(ttl(NextUpState::Final, "2021-03-21T09:00:00Z"), FINAL_TTL);
+        assert_eq!(
+            ttl(NextUpState::NoGames, "2021-03-21T12:00:00Z"),
+            OFF_DAY_TTL
+        );
+        assert_eq!(
+            ttl(NextUpState::Upcoming, "2021-03-21T17:00:00Z"),
+            GAME_DAY_TTL
+        );
+        assert_eq!(
+            ttl(NextUpState::Upcoming, "2021-03-23T17:00:00Z"),
+            OFF_DAY_TTL
+        );
+        // Never past the start of the game, and pregame polls stay quick.
+        assert_eq!(
+            ttl(NextUpState::Upcoming, "2021-03-21T12:02:00Z"),
+            Duration::from_secs(120)
+        );
+        assert_eq!(ttl(NextUpState::Upcoming, "2021-03-21T11:58:00Z"), LIVE_TTL);
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let cache = ResponseCache::default();
+        let schedule = CacheKey::new("club-schedule", 1);
+        let landing = CacheKey::new("landing", 1);
+        assert_eq!(cache.get(&schedule), None);
+
+        cache.insert(schedule, "schedule".to_string(), OFF_DAY_TTL);
+        cache.insert(landing, "landing".to_string(), Duration::from_secs(0));
+        assert_eq!(cache.get(&schedule).as_deref(), Some("schedule"));
+        assert_eq!(cache.get(&CacheKey::new("club-schedule", 28)), None);
+        assert_eq!(cache.get(&landing), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Where "now" comes from: the system clock, a fixed instant from `--now`, or
+//! the simulated clock of a replay. Debug builds also accept `?now=` on any
+//! route to see what a device would show at another moment.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use tide::http::Url;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// The instant given by a `?now=` query parameter, if any.
+pub fn now_param(url: &Url) -> Result<Option<DateTime<Utc>>> {
+    let now_param = url
+        .query_pairs()
+        .find(|(key, _)| key == "now")
+        .map(|(_, value)| value.to_string());
+    match now_param {
+        Some(now_param) => {
+            let now = DateTime::parse_from_rfc3339(&now_param)
+                .with_context(|| format!("not an RFC 3339 time: {}", now_param))?;
+            Ok(Some(now.with_timezone(&Utc)))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn url(query: &str) -> Url {
+        Url::parse(&format!("http://localhost/barracuda{}", query)).unwrap()
+    }
+
+    #[test]
+    fn test_fixed_clock() {
+        let then = DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock: Box<dyn Clock> = Box::new(FixedClock(then));
+        assert_eq!(clock.now(), then);
+        assert_eq!(clock.now(), then);
+    }
+
+    #[test]
+    fn test_now_param() {
+        assert_eq!(now_param(&url("")).unwrap(), None);
+        assert_eq!(now_param(&url("?tz=UTC")).unwrap(), None);
+        assert_eq!(
+            now_param(&url("?now=2023-10-13T12:00:00-07:00")).unwrap(),
+            Some(
+                DateTime::parse_from_rfc3339("2023-10-13T19:00:00Z")
+                    .unwrap()
+                    .with_timezone(&Utc)
+            )
+        );
+        assert!(now_param(&url("?now=tomorrow")).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+//! Server configuration. Settings come from an optional TOML file
+//! (`--config`), overridden by environment variables and then by flags, and
+//! are checked once at startup so a typo fails loudly instead of at the
+//! first request.
+
+use crate::{
+    ahl::BARRACUDA_SCHEDULE_URL, cache, error::from_toml, health::Upstream,
+    nhl_web::NHL_WEB_API_BASE, tickets, DEFAULT_TIME_ZONE, EVENTS_URL, RETRY_SLEEP, SHARKS_ID,
+    TEAM_NICKNAMES,
+};
+use anyhow::{bail, Context, Error};
+use chrono_tz::Tz;
+use http_types::Url;
+use serde::Deserialize;
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:8080";
+const DEFAULT_TICKETS: &str = "data/tickets.toml";
+const NHL_STATS_API_BASE: &str = "https://statsapi.web.nhl.com/api/v1";
+const DEFAULT_STATE_DIR: &str = "/tmp/magtag_gateway";
+const DEFAULT_MAX_STALE_HOURS: i64 = 12;
+
+/// How long upstream responses stay fresh, by the state of the game they
+/// describe.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheTtls {
+    pub live_secs: u64,
+    pub game_day_secs: u64,
+    pub final_secs: u64,
+    pub off_day_secs: u64,
+}
+
+impl Default for CacheTtls {
+    fn default() -> Self {
+        Self {
+            live_secs: cache::LIVE_TTL.as_secs(),
+            game_day_secs: cache::GAME_DAY_TTL.as_secs(),
+            final_secs: cache::FINAL_TTL.as_secs(),
+            off_day_secs: cache::OFF_DAY_TTL.as_secs(),
+        }
+    }
+}
+
+impl CacheTtls {
+    pub fn live(&self) -> Duration {
+        Duration::from_secs(self.live_secs)
+    }
+
+    pub fn game_day(&self) -> Duration {
+        Duration::from_secs(self.game_day_secs)
+    }
+
+    pub fn final_game(&self) -> Duration {
+        Duration::from_secs(self.final_secs)
+    }
+
+    pub fn off_day(&self) -> Duration {
+        Duration::from_secs(self.off_day_secs)
+    }
+}
+
+/// How long devices are told to sleep between polls.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SleepPolicy {
+    /// Polling interval in the last stretch before a game and during it.
+    pub pregame_secs: i64,
+    /// How long before a game devices wake up to start polling quickly.
+    pub wake_before_game_secs: i64,
+    /// The longest a device sleeps, and the sleep once a game has started.
+    pub max_secs: i64,
+    /// Sleep after an upstream failure.
+    pub retry_secs: i64,
+    /// Sleep when there is nothing on the schedule.
+    pub no_games_secs: i64,
+    /// Sleep while showing standings, which only change overnight.
+    pub standings_secs: i64,
+}
+
+impl Default for SleepPolicy {
+    fn default() -> Self {
+        Self {
+            pregame_secs: 60,
+            wake_before_game_secs: 20 * 60,
+            max_secs: 2 * 60 * 60,
+            retry_secs: RETRY_SLEEP,
+            no_games_secs: 15 * 60,
+            standings_secs: 60 * 60,
+        }
+    }
+}
+
+/// Base URLs of upstream services, to point at a mirror or a local fake.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamUrls {
+    pub nhl_web: String,
+    pub nhl_stats: String,
+    pub barracuda: String,
+}
+
+impl Default for UpstreamUrls {
+    fn default() -> Self {
+        Self {
+            nhl_web: NHL_WEB_API_BASE.to_string(),
+            nhl_stats: NHL_STATS_API_BASE.to_string(),
+            barracuda: BARRACUDA_SCHEDULE_URL.to_string(),
+        }
+    }
+}
+
+/// When `/readyz` reports not ready.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Readiness {
+    /// Upstreams that must be answering.
+    pub upstreams: Vec<Upstream>,
+    /// How long, in minutes, a required upstream may keep failing.
+    pub failure_minutes: i64,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self {
+            upstreams: vec![Upstream::Nhl, Upstream::Events, Upstream::Ahl],
+            failure_minutes: 15,
+        }
+    }
+}
+
+impl Readiness {
+    pub fn failure_threshold(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.failure_minutes)
+    }
+}
+
+/// The config file as written; every setting is optional.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    listen: Option<String>,
+    team: Option<usize>,
+    tz: Option<String>,
+    events: Option<String>,
+    tickets: Option<String>,
+    state_dir: Option<PathBuf>,
+    max_stale_hours: Option<i64>,
+    #[serde(default)]
+    cache: CacheTtls,
+    #[serde(default)]
+    sleep: SleepPolicy,
+    #[serde(default)]
+    upstreams: UpstreamUrls,
+    #[serde(default)]
+    ready: Readiness,
+}
+
+/// Settings from the environment and flags, which win over the file.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub listen: Option<String>,
+    /// `PORT`, as set by Cloud Run; listens on all interfaces.
+    pub port: Option<String>,
+    pub team: Option<usize>,
+    pub tz: Option<Tz>,
+    pub events: Option<String>,
+    pub tickets: Option<String>,
+    pub state_dir: Option<PathBuf>,
+    pub max_stale_hours: Option<i64>,
+    pub ready_upstreams: Option<Vec<Upstream>>,
+    pub ready_failure_minutes: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub team: usize,
+    pub tz: Tz,
+    /// The events TOML, as a local path or URL.
+    pub events: String,
+    /// The ticket list, as a local path or URL.
+    pub tickets: String,
+    /// Where the last good response for each route is kept.
+    pub state_dir: PathBuf,
+    /// How old, in hours, a saved response may be and still be served.
+    pub max_stale_hours: i64,
+    pub cache: CacheTtls,
+    pub sleep: SleepPolicy,
+    pub upstreams: UpstreamUrls,
+    pub ready: Readiness,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: DEFAULT_LISTEN.parse().expect("default listen address"),
+            team: SHARKS_ID,
+            tz: DEFAULT_TIME_ZONE,
+            events: EVENTS_URL.to_string(),
+            tickets: DEFAULT_TICKETS.to_string(),
+            state_dir: PathBuf::from(DEFAULT_STATE_DIR),
+            max_stale_hours: DEFAULT_MAX_STALE_HOURS,
+            cache: CacheTtls::default(),
+            sleep: SleepPolicy::default(),
+            upstreams: UpstreamUrls::default(),
+            ready: Readiness::default(),
+        }
+    }
+}
+
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+impl Config {
+    /// Read `path`, if given, and apply `overrides` on top.
+    pub fn load(path: Option<&Path>, overrides: Overrides) -> Result<Self, Error> {
+        let file = match path {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("reading config {}", path.display()))?;
+                from_toml("config", &text)
+                    .with_context(|| format!("in config {}", path.display()))?
+            }
+            None => ConfigFile::default(),
+        };
+        Self::resolve(file, overrides)
+    }
+
+    fn resolve(file: ConfigFile, overrides: Overrides) -> Result<Self, Error> {
+        let mut problems = Vec::new();
+        let Overrides {
+            listen,
+            port,
+            team,
+            tz,
+            events,
+            tickets,
+            state_dir,
+            max_stale_hours,
+            ready_upstreams,
+            ready_failure_minutes,
+        } = overrides;
+
+        let listen = listen
+            .or_else(|| port.map(|port| format!("0.0.0.0:{}", port)))
+            .or(file.listen)
+            .unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+        let listen = listen.parse().unwrap_or_else(|err| {
+            problems.push(format!("listen: {:?} is not an address: {}", listen, err));
+            Config::default().listen
+        });
+
+        let team = team.or(file.team).unwrap_or(SHARKS_ID);
+        if !TEAM_NICKNAMES.contains_key(&team) {
+            problems.push(format!("team: {} is not an NHL team id", team));
+        }
+
+        let tz = match (tz, file.tz) {
+            (Some(tz), _) => tz,
+            (None, Some(tz)) => tz.parse().unwrap_or_else(|err| {
+                problems.push(format!("tz: {}", err));
+                DEFAULT_TIME_ZONE
+            }),
+            (None, None) => DEFAULT_TIME_ZONE,
+        };
+
+        let events = events
+            .or(file.events)
+            .unwrap_or_else(|| EVENTS_URL.to_string());
+        check_source("events", &events, &mut problems);
+
+        let tickets = tickets
+            .or(file.tickets)
+            .unwrap_or_else(|| DEFAULT_TICKETS.to_string());
+        check_source("tickets", &tickets, &mut problems);
+        if !is_url(&tickets) {
+            if let Ok(text) = fs::read_to_string(&tickets) {
+                if let Err(err) = tickets::parse_tickets(&text) {
+                    problems.push(format!("tickets: {}: {:#}", tickets, err));
+                }
+            }
+        }
+
+        let state_dir = state_dir
+            .or(file.state_dir)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_DIR));
+        if state_dir.as_os_str().is_empty() {
+            problems.push("state_dir: must not be empty".to_string());
+        }
+
+        let max_stale_hours = max_stale_hours
+            .or(file.max_stale_hours)
+            .unwrap_or(DEFAULT_MAX_STALE_HOURS);
+        if max_stale_hours <= 0 {
+            problems.push("max_stale_hours: must be more than 0".to_string());
+        }
+
+        let cache = file.cache;
+        for (name, secs) in &[
+            ("live_secs", cache.live_secs),
+            ("game_day_secs", cache.game_day_secs),
+            ("final_secs", cache.final_secs),
+            ("off_day_secs", cache.off_day_secs),
+        ] {
+            if *secs == 0 {
+                problems.push(format!("cache.{}: must be more than 0", name));
+            }
+        }
+
+        let sleep = file.sleep;
+        for (name, secs) in &[
+            ("pregame_secs", sleep.pregame_secs),
+            ("max_secs", sleep.max_secs),
+            ("retry_secs", sleep.retry_secs),
+            ("no_games_secs", sleep.no_games_secs),
+            ("standings_secs", sleep.standings_secs),
+        ] {
+            if *secs <= 0 {
+                problems.push(format!("sleep.{}: must be more than 0", name));
+            }
+        }
+        if sleep.wake_before_game_secs < 0 {
+            problems.push("sleep.wake_before_game_secs: must not be negative".to_string());
+        }
+        if sleep.max_secs < sleep.pregame_secs {
+            problems.push(format!(
+                "sleep.max_secs: {} is less than pregame_secs {}",
+                sleep.max_secs, sleep.pregame_secs
+            ));
+        }
+
+        let upstreams = file.upstreams;
+        for (name, url) in &[
+            ("nhl_web", &upstreams.nhl_web),
+            ("nhl_stats", &upstreams.nhl_stats),
+            ("barracuda", &upstreams.barracuda),
+        ] {
+            if !is_url(url) || Url::parse(url).is_err() {
+                problems.push(format!(
+                    "upstreams.{}: {:?} is not an http(s) URL",
+                    name, url
+                ));
+            }
+        }
+
+        let ready = Readiness {
+            upstreams: ready_upstreams.unwrap_or(file.ready.upstreams),
+            failure_minutes: ready_failure_minutes.unwrap_or(file.ready.failure_minutes),
+        };
+        if ready.failure_minutes <= 0 {
+            problems.push("ready.failure_minutes: must be more than 0".to_string());
+        }
+
+        if !problems.is_empty() {
+            bail!("invalid configuration:\n  {}", problems.join("\n  "));
+        }
+        Ok(Self {
+            listen,
+            team,
+            tz,
+            events,
+            tickets,
+            state_dir,
+            max_stale_hours,
+            cache,
+            sleep,
+            upstreams,
+            ready,
+        })
+    }
+}
+
+/// URLs must parse; local paths must exist.
+fn check_source(name: &str, source: &str, problems: &mut Vec<String>) {
+    if is_url(source) {
+        if let Err(err) = Url::parse(source) {
+            problems.push(format!("{}: {:?} is not a URL: {}", name, source, err));
+        }
+    } else if !Path::new(source).is_file() {
+        problems.push(format!("{}: no file at {:?}", name, source));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE_TEXT: &str = include_str!("../magtag_gateway.example.toml");
+
+    fn file(text: &str) -> ConfigFile {
+        from_toml("config", text).expect("config file")
+    }
+
+    fn problems(text: &str, overrides: Overrides) -> String {
+        format!(
+            "{:#}",
+            Config::resolve(file(text), overrides).expect_err("invalid config")
+        )
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::resolve(ConfigFile::default(), Overrides::default()).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_example() {
+        let config = Config::resolve(file(EXAMPLE_TEXT), Overrides::default()).unwrap();
+        assert_eq!(config.team, 28);
+        assert_eq!(config.cache, CacheTtls::default());
+        assert_eq!(config.sleep, SleepPolicy::default());
+        assert_eq!(config.upstreams, UpstreamUrls::default());
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let text = r#"
+            listen = "127.0.0.1:9000"
+            team = 1
+            tz = "America/New_York"
+
+            max_stale_hours = 6
+
+            [cache]
+            live_secs = 30
+
+            [ready]
+            upstreams = ["nhl"]
+        "#;
+        let config = Config::resolve(file(text), Overrides::default()).unwrap();
+        assert_eq!(config.listen.to_string(), "127.0.0.1:9000");
+        assert_eq!(config.team, 1);
+        assert_eq!(config.tz, chrono_tz::America::New_York);
+        assert_eq!(config.cache.live(), Duration::from_secs(30));
+        assert_eq!(config.cache.final_game(), cache::FINAL_TTL);
+        assert_eq!(config.max_stale_hours, 6);
+        assert_eq!(config.ready.upstreams, [Upstream::Nhl]);
+        assert_eq!(config.ready.failure_minutes, 15);
+
+        let config = Config::resolve(
+            file(text),
+            Overrides {
+                port: Some("8081".to_string()),
+                team: Some(22),
+                tz: Some(chrono_tz::America::Edmonton),
+                max_stale_hours: Some(24),
+                ready_upstreams: Some(vec![Upstream::Ahl, Upstream::Events]),
+                ready_failure_minutes: Some(30),
+                ..Overrides::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(config.listen.to_string(), "0.0.0.0:8081");
+        assert_eq!(config.team, 22);
+        assert_eq!(config.tz, chrono_tz::America::Edmonton);
+        assert_eq!(config.max_stale_hours, 24);
+        assert_eq!(config.ready.upstreams, [Upstream::Ahl, Upstream::Events]);
+        assert_eq!(config.ready.failure_minutes, 30);
+
+        let config = Config::resolve(
+            file(text),
+            Overrides {
+                listen: Some("127.0.0.1:7000".to_string()),
+                port: Some("8081".to_string()),
+                ..Overrides::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(config.listen.to_string(), "127.0.0.1:7000");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let text = r#"
+            listen = "localhost"
+            team = 99
+            tz = "US/Pacifc"
+            events = "missing/events.toml"
+            max_stale_hours = 0
+
+            [sleep]
+            pregame_secs = 0
+
+            [ready]
+            failure_minutes = -5
+
+            [upstreams]
+            nhl_web = "api-web.nhle.com/v1"
+        "#;
+        let problems = problems(text, Overrides::default());
+        assert!(problems.contains("listen: \"localhost\" is not an address"));
+        assert!(problems.contains("team: 99 is not an NHL team id"));
+        assert!(problems.contains("tz: "));
+        assert!(problems.contains("events: no file at \"missing/events.toml\""));
+        assert!(problems.contains("sleep.pregame_secs: must be more than 0"));
+        assert!(problems.contains("max_stale_hours: must be more than 0"));
+        assert!(problems.contains("ready.failure_minutes: must be more than 0"));
+        assert!(
+            problems.contains("upstreams.nhl_web: \"api-web.nhle.com/v1\" is not an http(s) URL")
+        );
+    }
+
+    #[test]
+    fn test_unknown_setting() {
+        let err = from_toml::<ConfigFile>("config", "[cache]\nlive = 30\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `live`"));
+    }
+}
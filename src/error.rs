@@ -0,0 +1,57 @@
+//! Errors from fetching and interpreting upstream data. The engine returns
+//! these rather than panicking, so a bad payload costs one request at most.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("fetching {url}: {message}")]
+    Upstream { url: String, message: String },
+
+    #[error("parsing {what}: {source}")]
+    Json {
+        what: &'static str,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("parsing {what}: {source}")]
+    Toml {
+        what: &'static str,
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("unexpected {what}: {message}")]
+    DataShape { what: &'static str, message: String },
+}
+
+impl EngineError {
+    pub fn upstream(url: &str, message: impl ToString) -> Self {
+        EngineError::Upstream {
+            url: url.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn data_shape(what: &'static str, message: impl ToString) -> Self {
+        EngineError::DataShape {
+            what,
+            message: message.to_string(),
+        }
+    }
+}
+
+pub fn from_json<'a, T: serde::Deserialize<'a>>(
+    what: &'static str,
+    text: &'a str,
+) -> Result<T, EngineError> {
+    serde_json::from_str(text).map_err(|source| EngineError::Json { what, source })
+}
+
+pub fn from_toml<'a, T: serde::Deserialize<'a>>(
+    what: &'static str,
+    text: &'a str,
+) -> Result<T, EngineError> {
+    toml::from_str(text).map_err(|source| EngineError::Toml { what, source })
+}
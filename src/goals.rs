@@ -0,0 +1,275 @@
+//! Goal alerts. Devices only see one response at a time, so the gateway
+//! remembers the last score it served for each team and flags the polls that
+//! see it change. Several devices and routes share one tracker, so an alert
+//! stays up for a window long enough for each of them to poll once.
+
+use crate::{GameInfo, GameTeam, NextUp};
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
+
+/// The teams and score of a game; the scores are missing until it starts.
+#[derive(Debug, Clone)]
+pub struct Scoreboard {
+    pub game_id: usize,
+    pub home: GameTeam,
+    pub away: GameTeam,
+}
+
+impl Scoreboard {
+    pub fn from_game(game: &GameInfo) -> Self {
+        Self {
+            game_id: game.game_id,
+            home: game.home.clone(),
+            away: game.away.clone(),
+        }
+    }
+}
+
+/// Sent on the polls that see a goal, for the tracker's window.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// Abbreviation of the team that scored.
+    pub team: String,
+    /// Whether the team that scored is the one being followed.
+    pub ours: bool,
+    pub home_score: usize,
+    pub away_score: usize,
+}
+
+fn goal_alert(previous: &Scoreboard, current: &Scoreboard, team_id: usize) -> Option<Alert> {
+    // A game that hasn't started counts as 0-0.
+    let score = |team: &GameTeam| team.score.unwrap_or_default();
+    let home_scored = score(&current.home) > score(&previous.home);
+    let away_scored = score(&current.away) > score(&previous.away);
+    let scorer = match (home_scored, away_scored) {
+        // Both scored between polls; ours is the one worth celebrating.
+        (true, true) if current.away.id == team_id => &current.away,
+        (true, _) => &current.home,
+        (false, true) => &current.away,
+        (false, false) => return None,
+    };
+    Some(Alert {
+        team: scorer.abbreviation.clone(),
+        ours: scorer.id == team_id,
+        home_score: current.home.score.unwrap_or_default(),
+        away_score: current.away.score.unwrap_or_default(),
+    })
+}
+
+struct Seen {
+    scoreboard: Scoreboard,
+    /// The latest goal and when it was first seen.
+    goal: Option<(Alert, DateTime<Utc>)>,
+}
+
+/// The last score seen for each team. With the default window of zero only
+/// the first poll to see a goal alerts.
+pub struct GoalTracker {
+    window: Duration,
+    last: Mutex<HashMap<usize, Seen>>,
+}
+
+impl Default for GoalTracker {
+    fn default() -> Self {
+        Self::new(Duration::zero())
+    }
+}
+
+impl GoalTracker {
+    /// A tracker that keeps each alert up for `window` after the goal is
+    /// first seen.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: Mutex::default(),
+        }
+    }
+
+    /// Attach an alert to `next` if its score has gone up since the last
+    /// `NextUp` seen for `team_id`, or went up less than the window before
+    /// `utc_now`. A game first seen after it started doesn't alert, so a
+    /// restart mid-game stays quiet, and a score that goes down after a
+    /// review is just remembered.
+    pub fn observe(&self, team_id: usize, next: NextUp, utc_now: &DateTime<Utc>) -> NextUp {
+        let scoreboard = match &next.scoreboard {
+            Some(scoreboard) => scoreboard.clone(),
+            None => return next,
+        };
+        let mut last = self.last.lock().expect("goal tracker");
+        let previous = last
+            .remove(&team_id)
+            .filter(|seen| seen.scoreboard.game_id == scoreboard.game_id);
+        let goal = match previous {
+            Some(seen) => match goal_alert(&seen.scoreboard, &scoreboard, team_id) {
+                Some(alert) => {
+                    info!(
+                        "goal {} in game {}: {}-{}",
+                        alert.team, scoreboard.game_id, alert.home_score, alert.away_score
+                    );
+                    Some((alert, *utc_now))
+                }
+                // Still up until the window closes, unless the score has
+                // changed since.
+                None => seen.goal.filter(|(alert, seen_at)| {
+                    *utc_now - *seen_at < self.window
+                        && Some(alert.home_score) == scoreboard.home.score
+                        && Some(alert.away_score) == scoreboard.away.score
+                }),
+            },
+            None => None,
+        };
+        let alert = goal.as_ref().map(|(alert, _)| alert.clone());
+        last.insert(team_id, Seen { scoreboard, goal });
+        NextUp { alert, ..next }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{nhl_web::Extras, DEFAULT_TIME_ZONE};
+    use chrono::{DateTime, Utc};
+
+    const NJD_SCHEDULE_TEXT: &str = include_str!("../data/web_NJD_schedule.json");
+    const NJD_BEFORE_LANDING_TEXT: &str = include_str!("../data/web_NJD_before_landing.json");
+    const NJD_LANDINGS: &[&str] = &[
+        include_str!("../data/web_NJD_during_01_landing.json"),
+        include_str!("../data/web_NJD_during_02_landing.json"),
+        include_str!("../data/web_NJD_during_03_landing.json"),
+        include_str!("../data/web_NJD_during_04_landing.json"),
+        include_str!("../data/web_NJD_during_05_landing.json"),
+        include_str!("../data/web_NJD_during_06_landing.json"),
+        include_str!("../data/web_NJD_during_07_landing.json"),
+        include_str!("../data/web_NJD_after_landing.json"),
+    ];
+
+    fn njd_game_time() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn njd_next_up(landing: &str) -> NextUp {
+        let now = njd_game_time();
+        NextUp::new_web(
+            "Devils",
+            NJD_SCHEDULE_TEXT,
+            Some(landing),
+            Extras::default(),
+            1,
+            &now,
+            DEFAULT_TIME_ZONE,
+        )
+        .unwrap()
+    }
+
+    fn alert(team: &str, ours: bool, home_score: usize, away_score: usize) -> Option<Alert> {
+        Some(Alert {
+            team: team.to_string(),
+            ours,
+            home_score,
+            away_score,
+        })
+    }
+
+    #[test]
+    fn test_alerts_once_per_goal() {
+        let goals = GoalTracker::default();
+        let now = njd_game_time();
+        assert_eq!(
+            goals
+                .observe(1, njd_next_up(NJD_BEFORE_LANDING_TEXT), &now)
+                .alert,
+            None
+        );
+
+        // Each fixture is polled twice; only the first poll of a new score
+        // alerts, starting from 0-0 before the game.
+        let alerts: Vec<Option<Alert>> = NJD_LANDINGS
+            .iter()
+            .flat_map(|landing| vec![landing, landing])
+            .map(|landing| goals.observe(1, njd_next_up(landing), &now).alert)
+            .collect();
+        let mut expected = vec![None; alerts.len()];
+        expected[0] = alert("PIT", false, 1, 0);
+        expected[6] = alert("NJD", true, 1, 1);
+        expected[14] = alert("NJD", true, 1, 2);
+        assert_eq!(alerts, expected);
+    }
+
+    #[test]
+    fn test_alert_window() {
+        let goals = GoalTracker::new(Duration::seconds(60));
+        let at = |secs| njd_game_time() + Duration::seconds(secs);
+        goals.observe(1, njd_next_up(NJD_LANDINGS[2]), &at(0));
+        // Every poll within the window sees the goal, from any route.
+        for secs in &[10, 30, 69] {
+            assert_eq!(
+                goals
+                    .observe(1, njd_next_up(NJD_LANDINGS[3]), &at(*secs))
+                    .alert,
+                alert("NJD", true, 1, 1)
+            );
+        }
+        assert_eq!(
+            goals
+                .observe(1, njd_next_up(NJD_LANDINGS[3]), &at(70))
+                .alert,
+            None
+        );
+
+        // A goal taken back ends its alert early.
+        goals.observe(1, njd_next_up(NJD_LANDINGS[7]), &at(100));
+        assert_eq!(
+            goals
+                .observe(1, njd_next_up(NJD_LANDINGS[3]), &at(110))
+                .alert,
+            None
+        );
+    }
+
+    #[test]
+    fn test_first_poll_and_other_teams() {
+        let goals = GoalTracker::default();
+        // Joining mid-game doesn't alert for goals already scored.
+        assert_eq!(
+            goals
+                .observe(1, njd_next_up(NJD_LANDINGS[7]), &njd_game_time())
+                .alert,
+            None
+        );
+        // Each team is tracked separately.
+        assert_eq!(
+            goals
+                .observe(5, njd_next_up(NJD_LANDINGS[3]), &njd_game_time())
+                .alert,
+            None
+        );
+        assert_eq!(
+            goals
+                .observe(5, njd_next_up(NJD_LANDINGS[7]), &njd_game_time())
+                .alert,
+            alert("NJD", false, 1, 2)
+        );
+    }
+
+    #[test]
+    fn test_overturned_goal() {
+        let goals = GoalTracker::default();
+        goals.observe(1, njd_next_up(NJD_LANDINGS[4]), &njd_game_time());
+        assert_eq!(
+            goals
+                .observe(1, njd_next_up(NJD_LANDINGS[0]), &njd_game_time())
+                .alert,
+            None
+        );
+        assert_eq!(
+            goals
+                .observe(1, njd_next_up(NJD_LANDINGS[4]), &njd_game_time())
+                .alert,
+            alert("NJD", true, 1, 1)
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Upstream health for `/readyz`. Every fetch records its outcome, and a
+//! background task probes upstreams that normal traffic hasn't touched
+//! lately, so readiness reflects the sources rather than just the process.
+
+use crate::{
+    config::{self, Config},
+    fetch_unrecorded, standings,
+    state::AppState,
+};
+use anyhow::{bail, Error};
+use async_std::task;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+/// How often upstreams are checked when no request has touched them.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const PROBE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Upstream {
+    /// The NHL web API: schedules, game landings, standings and playoffs.
+    Nhl,
+    /// The events TOML feed.
+    Events,
+    /// The Barracuda's AHL schedule page.
+    Ahl,
+}
+
+impl Upstream {
+    /// The upstream behind a fetch `provider`, as labelled in metrics.
+    /// Tickets are local configuration rather than a source.
+    pub fn for_provider(provider: &str) -> Option<Self> {
+        match provider {
+            "events" => Some(Upstream::Events),
+            "barracuda" => Some(Upstream::Ahl),
+            "tickets" => None,
+            _ => Some(Upstream::Nhl),
+        }
+    }
+
+    /// A cheap request that shows whether the upstream is answering, or
+    /// `None` when it is configured as a local file.
+    fn probe(self, config: &Config) -> Option<(&'static str, String)> {
+        match self {
+            Upstream::Nhl => Some((
+                "standings",
+                standings::standings_url(&config.upstreams.nhl_web),
+            )),
+            Upstream::Events if config::is_url(&config.events) => {
+                Some(("events", config.events.clone()))
+            }
+            Upstream::Events => None,
+            Upstream::Ahl => Some(("barracuda", config.upstreams.barracuda.clone())),
+        }
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Upstream::Nhl => "nhl",
+            Upstream::Events => "events",
+            Upstream::Ahl => "ahl",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "nhl" => Ok(Upstream::Nhl),
+            "events" => Ok(Upstream::Events),
+            "ahl" => Ok(Upstream::Ahl),
+            _ => bail!("unknown upstream {:?}, expected nhl, events or ahl", s),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct UpstreamStatus {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// How long the most recent fetch took, successful or not.
+    pub latency_ms: Option<u64>,
+    /// When the current run of failures started.
+    pub failing_since: Option<DateTime<Utc>>,
+}
+
+impl UpstreamStatus {
+    fn record(&mut self, result: Result<(), String>, latency: Duration, at: DateTime<Utc>) {
+        self.latency_ms = Some(latency.as_millis() as u64);
+        match result {
+            Ok(()) => {
+                self.last_success = Some(at);
+                self.failing_since = None;
+            }
+            Err(error) => {
+                self.last_failure = Some(at);
+                self.last_error = Some(error);
+                self.failing_since.get_or_insert(at);
+            }
+        }
+    }
+
+    fn last_checked(&self) -> Option<DateTime<Utc>> {
+        self.last_success.max(self.last_failure)
+    }
+
+    /// Whether the upstream has failed every fetch for longer than
+    /// `threshold`.
+    fn is_failing(&self, threshold: chrono::Duration, now: DateTime<Utc>) -> bool {
+        match self.failing_since {
+            Some(failing_since) => now - failing_since > threshold,
+            None => false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    pub ready: bool,
+    pub upstreams: BTreeMap<Upstream, UpstreamStatus>,
+}
+
+#[derive(Default)]
+pub struct Health {
+    upstreams: Mutex<HashMap<Upstream, UpstreamStatus>>,
+}
+
+impl Health {
+    pub fn record(
+        &self,
+        upstream: Upstream,
+        result: Result<(), String>,
+        latency: Duration,
+        at: DateTime<Utc>,
+    ) {
+        self.upstreams
+            .lock()
+            .expect("upstream health")
+            .entry(upstream)
+            .or_default()
+            .record(result, latency, at);
+    }
+
+    /// Ready unless one of the `required` upstreams has been failing for
+    /// longer than `threshold`. Upstreams not yet checked count as ready,
+    /// so a new instance isn't held back by its first probe.
+    pub fn readiness(
+        &self,
+        required: &[Upstream],
+        threshold: chrono::Duration,
+        now: DateTime<Utc>,
+    ) -> Readiness {
+        let upstreams: BTreeMap<Upstream, UpstreamStatus> = self
+            .upstreams
+            .lock()
+            .expect("upstream health")
+            .iter()
+            .map(|(upstream, status)| (*upstream, status.clone()))
+            .collect();
+        let ready = required.iter().all(|upstream| {
+            !matches!(upstreams.get(upstream), Some(status) if status.is_failing(threshold, now))
+        });
+        Readiness { ready, upstreams }
+    }
+
+    fn needs_probe(&self, upstream: Upstream, now: DateTime<Utc>) -> bool {
+        let last_checked = self
+            .upstreams
+            .lock()
+            .expect("upstream health")
+            .get(&upstream)
+            .and_then(UpstreamStatus::last_checked);
+        match last_checked {
+            Some(last_checked) => {
+                (now - last_checked).to_std().unwrap_or_default() >= PROBE_INTERVAL
+            }
+            None => true,
+        }
+    }
+}
+
+/// Probe each of `upstreams` that hasn't been fetched from recently, for
+/// as long as the server runs. Results count towards health and metrics,
+/// but aren't saved with `--record`, since no device asked for them.
+pub async fn probe_upstreams(state: AppState, upstreams: Vec<Upstream>) {
+    loop {
+        for &upstream in &upstreams {
+            if !state.health.needs_probe(upstream, Utc::now()) {
+                continue;
+            }
+            if let Some((provider, url)) = upstream.probe(&state.config) {
+                if let Err(err) = fetch_unrecorded(&state, provider, &url).await {
+                    warn!("probing {}: {:#}", upstream, err);
+                }
+            }
+        }
+        task::sleep(PROBE_CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    const LATENCY: Duration = Duration::from_millis(120);
+
+    #[test]
+    fn test_upstream_names() {
+        assert_eq!("nhl".parse::<Upstream>().unwrap(), Upstream::Nhl);
+        assert_eq!("ahl".parse::<Upstream>().unwrap().to_string(), "ahl");
+        assert!("espn".parse::<Upstream>().is_err());
+        assert_eq!(Upstream::for_provider("landing"), Some(Upstream::Nhl));
+        assert_eq!(Upstream::for_provider("barracuda"), Some(Upstream::Ahl));
+        assert_eq!(Upstream::for_provider("tickets"), None);
+    }
+
+    #[test]
+    fn test_readiness() {
+        let health = Health::default();
+        let threshold = chrono::Duration::minutes(15);
+        let required = [Upstream::Nhl, Upstream::Events];
+        let ready = |now| health.readiness(&required, threshold, at(now)).ready;
+        assert!(ready("2021-03-21T17:00:00Z"));
+
+        health.record(Upstream::Nhl, Ok(()), LATENCY, at("2021-03-21T17:00:00Z"));
+        health.record(
+            Upstream::Nhl,
+            Err("HTTP 503".to_string()),
+            LATENCY,
+            at("2021-03-21T17:05:00Z"),
+        );
+        health.record(
+            Upstream::Nhl,
+            Err("HTTP 503".to_string()),
+            LATENCY,
+            at("2021-03-21T17:10:00Z"),
+        );
+        assert!(ready("2021-03-21T17:15:00Z"));
+        assert!(!ready("2021-03-21T17:21:00Z"));
+
+        // Upstreams that aren't required don't count.
+        health.record(
+            Upstream::Ahl,
+            Err("timed out".to_string()),
+            LATENCY,
+            at("2021-03-21T12:00:00Z"),
+        );
+        health.record(Upstream::Nhl, Ok(()), LATENCY, at("2021-03-21T17:22:00Z"));
+        let readiness = health.readiness(&required, threshold, at("2021-03-21T17:23:00Z"));
+        assert!(readiness.ready);
+        let nhl = &readiness.upstreams[&Upstream::Nhl];
+        assert_eq!(nhl.failing_since, None);
+        assert_eq!(nhl.last_failure, Some(at("2021-03-21T17:10:00Z")));
+        assert_eq!(nhl.last_error.as_deref(), Some("HTTP 503"));
+        assert_eq!(nhl.latency_ms, Some(120));
+        assert!(!readiness.upstreams.contains_key(&Upstream::Events));
+        let json = serde_json::to_value(&readiness).unwrap();
+        assert_eq!(json["upstreams"]["nhl"]["latency_ms"], 120);
+    }
+
+    #[test]
+    fn test_needs_probe() {
+        let health = Health::default();
+        assert!(health.needs_probe(Upstream::Events, at("2021-03-21T17:00:00Z")));
+        health.record(
+            Upstream::Events,
+            Ok(()),
+            LATENCY,
+            at("2021-03-21T17:00:00Z"),
+        );
+        assert!(!health.needs_probe(Upstream::Events, at("2021-03-21T17:04:00Z")));
+        assert!(health.needs_probe(Upstream::Events, at("2021-03-21T17:05:00Z")));
+    }
+}
@@ -0,0 +1,178 @@
+//! The last successful `NextUp` for each route, kept on disk so a restart or
+//! an upstream outage doesn't blank the screen.
+
+use crate::NextUp;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Saved {
+    saved_at: DateTime<Utc>,
+    next_up: NextUp,
+}
+
+pub struct LastGood {
+    dir: PathBuf,
+    max_age: Duration,
+}
+
+impl LastGood {
+    pub fn new(dir: &Path, max_age: Duration) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            max_age,
+        }
+    }
+
+    /// Routes include team ids and source lists; keep file names tame.
+    fn path(&self, key: &str) -> PathBuf {
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.dir.join(format!("{}.json", name))
+    }
+
+    pub fn save(&self, key: &str, next_up: &NextUp, utc_now: &DateTime<Utc>) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating {}", self.dir.display()))?;
+        let path = self.path(key);
+        let saved = Saved {
+            saved_at: *utc_now,
+            next_up: next_up.clone(),
+        };
+        // Write then rename, so a crash mid-write can't leave a torn file.
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string(&saved)?)
+            .with_context(|| format!("writing {}", temp_path.display()))?;
+        fs::rename(&temp_path, &path).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+
+    /// The saved `NextUp` for `key`, unless there is none or it is older
+    /// than the limit.
+    pub fn load(&self, key: &str, utc_now: &DateTime<Utc>) -> Result<Option<NextUp>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        let saved: Saved =
+            serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        if *utc_now - saved.saved_at > self.max_age {
+            return Ok(None);
+        }
+        Ok(Some(saved.next_up))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::SleepPolicy, FeedStatus, NextUpState, SourceKind, DEFAULT_TIME_ZONE, RETRY_SLEEP,
+    };
+    use std::env;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("magtag_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn upcoming() -> NextUp {
+        NextUp {
+            top: "Sharks Next Up".to_string(),
+            middle: "vs Minnesota Wild".to_string(),
+            bottom: "Mar 23 @ 7:30PM".to_string(),
+            sleep: 7200,
+            date: at("2021-03-24T02:30:00Z"),
+            state: NextUpState::Upcoming,
+            source: Some(SourceKind::Nhl),
+            ..NextUp::default()
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = temp_dir("last_good");
+        let last_good = LastGood::new(&dir, Duration::hours(12));
+        let saved_at = at("2021-03-21T17:00:00Z");
+
+        assert!(last_good.load("next/28", &saved_at).unwrap().is_none());
+        last_good.save("next/28", &upcoming(), &saved_at).unwrap();
+        assert!(dir.join("next_28.json").exists());
+
+        let loaded = last_good
+            .load("next/28", &at("2021-03-22T04:00:00Z"))
+            .unwrap()
+            .expect("saved");
+        assert_eq!(loaded.middle, "vs Minnesota Wild");
+        assert!(last_good.load("next/1", &saved_at).unwrap().is_none());
+        assert!(last_good
+            .load("next/28", &at("2021-03-22T05:00:01Z"))
+            .unwrap()
+            .is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_recomputes_relative_text() {
+        let game_day = at("2021-03-23T19:00:00Z");
+        let stale = upcoming().stale("offline", &game_day, DEFAULT_TIME_ZONE);
+        assert!(stale.stale);
+        assert_eq!(stale.status, FeedStatus::Degraded);
+        assert_eq!(stale.error.as_deref(), Some("offline"));
+        assert_eq!(stale.middle, "vs Minnesota Wild");
+        assert_eq!(stale.bottom, "Today @ 7:30PM");
+        assert_eq!(stale.time, "12:00PM");
+        let policy = SleepPolicy::default();
+        assert_eq!(stale.with_sleep(&policy, &game_day).sleep, RETRY_SLEEP);
+
+        // Once the game should have started, the old text is left alone.
+        let started = upcoming().stale("offline", &at("2021-03-24T03:00:00Z"), DEFAULT_TIME_ZONE);
+        assert_eq!(started.bottom, "Mar 23 @ 7:30PM");
+    }
+
+    #[test]
+    fn test_stale_time_tbd() {
+        let game_day = at("2021-03-23T19:00:00Z");
+        let tbd = NextUp {
+            bottom: "Mar 23".to_string(),
+            time_tbd: true,
+            ..upcoming()
+        };
+        assert_eq!(
+            tbd.stale("offline", &game_day, DEFAULT_TIME_ZONE).bottom,
+            "Today"
+        );
+
+        // Saved before the source was recorded: shown as it was saved.
+        let unknown: NextUp = serde_json::from_str(
+            r#"{"top":"Sharks Next Up","middle":"vs Minnesota Wild","bottom":"Mar 23 @ 7:30PM",
+                "time":"10:00AM","sleep":7200,"date":"2021-03-24T02:30:00Z",
+                "home_score":null,"away_score":null,"ticket":null,"state":"upcoming"}"#,
+        )
+        .unwrap();
+        assert_eq!(unknown.source, None);
+        assert_eq!(
+            unknown
+                .stale("offline", &game_day, DEFAULT_TIME_ZONE)
+                .bottom,
+            "Mar 23 @ 7:30PM"
+        );
+    }
+}
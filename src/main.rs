@@ -1,7 +1,8 @@
 use anyhow::{Context, Error, Result};
+use async_std::future;
 use chrono::{DateTime, Local, Utc};
-use chrono_tz::US::Pacific;
-use log::info;
+use chrono_tz::Tz;
+use log::{info, warn};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::{
@@ -9,16 +10,44 @@ use std::{
     env,
     fs::{self},
     path::PathBuf,
+    sync::Arc,
 };
 use structopt::StructOpt;
 
-const ONE_MINUTE_IN_SECONDS: i64 = 60;
-const ONE_HOUR_IN_SECONDS: i64 = 60 * ONE_MINUTE_IN_SECONDS;
-const TWO_HOURS_IN_SECONDS: i64 = 2 * ONE_HOUR_IN_SECONDS;
-const TWENTY_MINUTES_IN_SECONDS: i64 = 20 * ONE_MINUTE_IN_SECONDS;
+mod ahl;
+mod cache;
+mod clock;
+mod config;
+mod error;
+mod goals;
+mod health;
+mod last_good;
+mod metrics;
+mod mqtt;
+mod nhl_web;
+mod playoffs;
+mod render;
+mod replay;
+mod sources;
+mod standings;
+mod state;
+mod stream;
+mod tickets;
+
+use ahl::{load_games_from_list, AhlGame};
+use cache::{CacheFill, CacheKey};
+use config::SleepPolicy;
+use error::{from_json, from_toml, EngineError};
+use goals::{Alert, Scoreboard};
+use health::Upstream;
+use playoffs::SeriesRecord;
+use sources::{BarracudaSource, EventsSource, NhlSource, ScheduleSource};
+use state::AppState;
+use tickets::{League, Ticket};
 
 const CUDA_NEXT_UP: &str = "Cuda Next Up";
 const SHARKS_NEXT_UP: &str = "Sharks Next Up";
+const EVENTS_URL: &str = "https://storage.googleapis.com/magtag/events.toml";
 
 pub static TEAM_NICKNAMES: Lazy<HashMap<usize, &'static str>> = Lazy::new(|| {
     [
@@ -51,38 +80,56 @@ pub static TEAM_NICKNAMES: Lazy<HashMap<usize, &'static str>> = Lazy::new(|| {
         (29, "Jackets"),
         (30, "Wild"),
         (52, "Jets"),
-        (54, "Coyotes"),
-        (55, "Knights"),
-        (56, "Kraken"),
+        (53, "Coyotes"),
+        (54, "Knights"),
+        (55, "Kraken"),
+        (59, "Mammoth"),
     ]
     .iter()
     .cloned()
     .collect()
 });
 
-const GAMES_WITH_TICKETS: &[[&str; 2]] = &[
-    ["Icehogs", "2023-10-14T02:00:00Z"],
-    ["Icehogs", "2023-10-14T21:00:00Z"],
-    ["Firebirds", "2023-10-29T01:00:00Z"],
-    ["Checkers", "2023-11-11T02:00:00Z"],
-    ["Checkers", "2023-11-12T22:00:00Z"],
-    ["Eagles", "2023-11-19T01:00:00Z"],
-    ["Reign", "2023-11-25T22:00:00Z"],
-    ["Silver Knights", "2023-12-09T02:00:00Z"],
-    ["Silver Knights", "2023-12-10T01:00:00Z"],
-    ["Wranglers", "2023-12-17T01:00:00Z"],
-    ["Condors", "2023-12-31T01:00:00Z"],
-    ["Roadrunners", "2024-01-04T02:00:00Z"],
-    ["Condors", "2024-01-13T02:00:00Z"],
-    ["Wranglers", "2024-01-27T02:00:00Z"],
-    ["Wranglers", "2024-01-28T01:00:00Z"],
-    ["Firebirds", "2024-02-22T02:00:00Z"],
-    ["Canucks", "2024-02-24T21:00:00Z"],
-    ["Roadrunners", "2024-03-23T21:00:00Z"],
-    ["Reign", "2024-03-27T02:00:00Z"],
-    ["Stars", "2024-04-12T02:00:00Z"],
-    ["Stars", "2024-04-13T20:00:00Z"],
-];
+pub static TEAM_ABBREVIATIONS: Lazy<HashMap<usize, &'static str>> = Lazy::new(|| {
+    [
+        (1, "NJD"),
+        (2, "NYI"),
+        (3, "NYR"),
+        (4, "PHI"),
+        (5, "PIT"),
+        (6, "BOS"),
+        (7, "BUF"),
+        (8, "MTL"),
+        (9, "OTT"),
+        (10, "TOR"),
+        (12, "CAR"),
+        (13, "FLA"),
+        (14, "TBL"),
+        (15, "WSH"),
+        (16, "CHI"),
+        (17, "DET"),
+        (18, "NSH"),
+        (19, "STL"),
+        (20, "CGY"),
+        (21, "COL"),
+        (22, "EDM"),
+        (23, "VAN"),
+        (24, "ANA"),
+        (25, "DAL"),
+        (26, "LAK"),
+        (28, "SJS"),
+        (29, "CBJ"),
+        (30, "MIN"),
+        (52, "WPG"),
+        (53, "ARI"),
+        (54, "VGK"),
+        (55, "SEA"),
+        (59, "UTA"),
+    ]
+    .iter()
+    .cloned()
+    .collect()
+});
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, Ord, PartialEq, Eq)]
 struct Event {
@@ -107,10 +154,103 @@ struct Opt {
     #[structopt(short, long)]
     next: Option<PathBuf>,
 
-    #[structopt(short, long)]
+    /// Server configuration file; the flags and environment variables
+    /// here override it
+    #[structopt(long, env = "MAGTAG_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on; defaults to 0.0.0.0 on `PORT`, or 8080
+    #[structopt(long, env = "MAGTAG_LISTEN")]
+    listen: Option<String>,
+
+    /// Team shown by /next; defaults to the Sharks
+    #[structopt(short, long, env = "MAGTAG_TEAM")]
     team: Option<usize>,
+
+    /// Playoff series records to use instead of fetching the carousel
+    #[structopt(long)]
+    series: Option<PathBuf>,
+
+    /// Show both teams' records and streaks before a game
+    #[structopt(long)]
+    records: bool,
+
+    /// Use the retired statsapi.web.nhl.com endpoints instead of api-web.nhle.com
+    #[structopt(long)]
+    legacy: bool,
+
+    /// Time zone used for "today", relative dates and the displayed time;
+    /// defaults to US/Pacific. Individual requests can override it with
+    /// `?tz=America/New_York`.
+    #[structopt(long, env = "MAGTAG_TZ")]
+    tz: Option<Tz>,
+
+    /// The events TOML, as a local path or URL
+    #[structopt(long, env = "MAGTAG_EVENTS")]
+    events: Option<String>,
+
+    /// Games we hold tickets for, as a local path or URL to a TOML list;
+    /// defaults to data/tickets.toml
+    #[structopt(long, env = "MAGTAG_TICKETS")]
+    tickets: Option<String>,
+
+    /// Save every upstream response, with the time it was fetched, to this directory
+    #[structopt(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve upstream responses saved with --record, on a simulated clock
+    /// that steps forward with each request
+    #[structopt(long)]
+    replay: Option<PathBuf>,
+
+    /// Pretend it is this RFC 3339 time, e.g. 2021-03-21T17:00:00Z
+    #[structopt(long, conflicts_with = "replay")]
+    now: Option<DateTime<Utc>>,
+
+    /// Where the last good response for each route is kept, to serve when
+    /// upstream fails; defaults to /tmp/magtag_gateway
+    #[structopt(long, env = "MAGTAG_STATE_DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// How old, in hours, a saved response may be and still be served;
+    /// defaults to 12
+    #[structopt(long, env = "MAGTAG_MAX_STALE_HOURS")]
+    max_stale_hours: Option<i64>,
+
+    /// MQTT broker to publish game state and Home Assistant discovery to,
+    /// as host or host:port
+    #[structopt(long, env = "MAGTAG_MQTT_BROKER")]
+    mqtt_broker: Option<String>,
+
+    #[structopt(long, env = "MAGTAG_MQTT_USERNAME")]
+    mqtt_username: Option<String>,
+
+    #[structopt(long, env = "MAGTAG_MQTT_PASSWORD", hide_env_values = true)]
+    mqtt_password: Option<String>,
+
+    /// Teams to publish over MQTT, e.g. 28,1; defaults to --team
+    #[structopt(long, use_delimiter = true)]
+    mqtt_teams: Vec<usize>,
+
+    #[structopt(long, default_value = "magtag")]
+    mqtt_prefix: String,
+
+    #[structopt(long, default_value = "homeassistant")]
+    mqtt_discovery_prefix: String,
+
+    /// Upstreams that must be answering for /readyz to report ready;
+    /// defaults to nhl,events,ahl
+    #[structopt(long, env = "MAGTAG_READY_UPSTREAMS", use_delimiter = true)]
+    ready_upstreams: Option<Vec<Upstream>>,
+
+    /// How long, in minutes, a required upstream may keep failing before
+    /// /readyz reports not ready; defaults to 15
+    #[structopt(long, env = "MAGTAG_READY_FAILURE_MINUTES")]
+    ready_failure_minutes: Option<i64>,
 }
 
+const DEFAULT_TIME_ZONE: Tz = chrono_tz::US::Pacific;
+
 const SHARKS_ID: usize = 28;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -185,6 +325,71 @@ pub struct Game {
     pub linescore: Option<Linescore>,
 }
 
+impl Game {
+    fn game_state(&self) -> GameState {
+        if self.status.is_preview() {
+            if self.status.is_pregame() {
+                GameState::Pregame
+            } else {
+                GameState::Scheduled
+            }
+        } else if self.status.is_live() {
+            // Without a linescore all we can say is that the game is on.
+            let linescore = match self.linescore.as_ref() {
+                Some(linescore) => linescore,
+                None => {
+                    warn!("live game {} has no linescore", self.game_pk);
+                    return GameState::Live(LiveState {
+                        period_ordinal: None,
+                        time_remaining: None,
+                        intermission_remaining: None,
+                    });
+                }
+            };
+            let intermission_remaining = linescore
+                .intermission_info
+                .as_ref()
+                .filter(|intermission_info| intermission_info.in_intermission)
+                .map(|intermission_info| intermission_info.intermission_time_remaining);
+            GameState::Live(LiveState {
+                period_ordinal: linescore.current_period_ordinal.clone(),
+                time_remaining: linescore.current_period_time_remaining.clone(),
+                intermission_remaining,
+            })
+        } else {
+            GameState::Final
+        }
+    }
+
+    fn game_info(&self) -> GameInfo {
+        GameInfo {
+            game_id: self.game_pk,
+            date: self.game_date,
+            home: GameTeam::from(&self.teams.home),
+            away: GameTeam::from(&self.teams.away),
+            state: self.game_state(),
+            time_tbd: self.status.is_tbd(),
+            series: None,
+            special_event: None,
+        }
+    }
+}
+
+impl From<&TeamAtGame> for GameTeam {
+    fn from(team_at_game: &TeamAtGame) -> Self {
+        let id = team_at_game.team.id;
+        Self {
+            id,
+            name: team_at_game.team.name.clone(),
+            abbreviation: TEAM_ABBREVIATIONS
+                .get(&id)
+                .map(|abbreviation| abbreviation.to_string())
+                .unwrap_or_else(|| team_at_game.team.name.clone()),
+            score: team_at_game.score,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct GameDate {
@@ -200,15 +405,14 @@ pub struct NextGameSchedule {
 }
 
 impl NextGameSchedule {
-    fn game_today(&self, utc_now: &DateTime<Utc>) -> bool {
-        if self.total_items < 1 {
-            return false;
-        }
-        let game_date = &self.dates[0];
-        let game = &game_date.games[0];
-        let game_date_pacific = game.game_date.with_timezone(&Pacific);
-        let pacific_now = utc_now.with_timezone(&Pacific);
-        game_date_pacific.date() == pacific_now.date()
+    fn first_game(&self) -> Option<&Game> {
+        self.dates.first().and_then(|date| date.games.first())
+    }
+
+    fn todays_game(&self, utc_now: &DateTime<Utc>, tz: Tz) -> Option<&Game> {
+        let local_now = utc_now.with_timezone(&tz);
+        self.first_game()
+            .filter(|game| game.game_date.with_timezone(&tz).date() == local_now.date())
     }
 }
 
@@ -226,36 +430,161 @@ pub struct Response {
     pub teams: Vec<ScheduledTeam>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Provider-independent view of a single game, built from either NHL API.
+#[derive(Debug, Clone)]
+pub struct GameInfo {
+    pub game_id: usize,
+    pub date: DateTime<Utc>,
+    pub home: GameTeam,
+    pub away: GameTeam,
+    pub state: GameState,
+    pub time_tbd: bool,
+    /// The playoff series this game is part of, when known.
+    pub series: Option<SeriesRecord>,
+    /// A named event such as "Stadium Series" that the game is part of.
+    pub special_event: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameTeam {
+    pub id: usize,
+    pub name: String,
+    pub abbreviation: String,
+    pub score: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameState {
+    Scheduled,
+    Pregame,
+    Live(LiveState),
+    Final,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveState {
+    pub period_ordinal: Option<String>,
+    pub time_remaining: Option<String>,
+    /// Seconds left in the intermission, if the game is in one.
+    pub intermission_remaining: Option<usize>,
+}
+
+/// What a `NextUp` is showing, so callers combining several can tell a live
+/// game from an upcoming one, or from nothing at all.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum NextUpState {
+    #[default]
+    NoGames,
+    Upcoming,
+    Live,
+    Final,
+    /// Where the team stands, for days without a game.
+    Standings,
+}
+
+/// Which kind of upstream a `NextUp` was built from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SourceKind {
+    Nhl,
+    Ahl,
+    Events,
+    Standings,
+}
+
+/// Whether a `NextUp` was built from everything it needed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum FeedStatus {
+    #[default]
+    Ok,
+    /// Shown from partial or fallback data.
+    Degraded,
+    /// Nothing could be fetched; the text says so.
+    Error,
+}
+
+/// Default for the `retry_secs` sleep setting: how soon, in seconds, a device
+/// tries again when the gateway is degraded.
+const RETRY_SLEEP: i64 = 120;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct NextUp {
     top: String,
     middle: String,
     bottom: String,
     time: String,
+    /// Seconds until the device should poll again, set from the sleep policy
+    /// just before the `NextUp` is served.
     sleep: i64,
     date: DateTime<Utc>,
+    home_score: Option<usize>,
+    away_score: Option<usize>,
+    /// Our ticket for the game, only on game day.
+    ticket: Option<Ticket>,
+    #[serde(default)]
+    state: NextUpState,
+    /// Unknown in responses saved before it was recorded.
+    #[serde(default)]
+    source: Option<SourceKind>,
+    /// The start time hasn't been set, so `bottom` shows only the day.
+    #[serde(default)]
+    time_tbd: bool,
+    #[serde(default)]
+    status: FeedStatus,
+    /// Served from an earlier successful response rather than fresh data.
+    #[serde(default)]
+    stale: bool,
+    /// What went wrong upstream, when `status` isn't ok.
+    #[serde(default)]
+    error: Option<String>,
+    /// Where a playoff series stands, e.g. "SJS leads 2-1".
+    #[serde(default)]
+    series: Option<String>,
+    /// Both teams' records and streaks before a game, with `--records`.
+    #[serde(default)]
+    records: Option<String>,
+    /// Set on the first response to show a new goal.
+    #[serde(default)]
+    alert: Option<Alert>,
+    /// The game's score, for spotting goals between polls.
+    #[serde(skip)]
+    scoreboard: Option<Scoreboard>,
 }
 
-fn opponent_name(teams: &Teams, home_team: usize) -> String {
-    if teams.home.team.id == home_team {
-        format!("vs {}", teams.away.team.name)
+fn opponent_name(game: &GameInfo, home_team: usize) -> String {
+    if game.home.id == home_team {
+        format!("vs {}", game.away.name)
     } else {
-        format!("@ {}", teams.home.team.name)
+        format!("@ {}", game.home.name)
     }
 }
 
-fn format_date_time(date_time: &DateTime<chrono_tz::Tz>) -> String {
+/// The score with the followed team first, e.g. "SJS 3 - 2 MIN".
+fn score_line(game: &GameInfo, team_id: usize) -> Option<String> {
+    let (us, them) = if game.home.id == team_id {
+        (&game.home, &game.away)
+    } else {
+        (&game.away, &game.home)
+    };
+    Some(format!(
+        "{} {} - {} {}",
+        us.abbreviation, us.score?, them.score?, them.abbreviation
+    ))
+}
+
+fn format_date_time(date_time: &DateTime<Tz>) -> String {
     date_time.format("%-I:%M%p").to_string()
 }
 
 fn format_game_time_relative(
-    date_time: &DateTime<chrono_tz::Tz>,
-    utc_now: &DateTime<chrono_tz::Tz>,
+    date_time: &DateTime<Tz>,
+    local_now: &DateTime<Tz>,
     is_tdb: bool,
 ) -> String {
-    let date_pacific = date_time.with_timezone(&Pacific);
-    let pacific_now = utc_now.with_timezone(&Pacific);
-    if date_pacific.date() == pacific_now.date() {
+    let date_local = date_time.with_timezone(&local_now.timezone());
+    if date_local.date() == local_now.date() {
         if is_tdb {
             "Today".to_string()
         } else {
@@ -270,15 +599,15 @@ fn format_game_time_relative(
     }
 }
 
-fn sleep_time(date_time: &DateTime<chrono_tz::Tz>, utc_now: &DateTime<chrono_tz::Tz>) -> i64 {
+fn sleep_time(policy: &SleepPolicy, date_time: &DateTime<Utc>, utc_now: &DateTime<Utc>) -> i64 {
     let duration_until_game = *date_time - *utc_now;
     let duration_until_game_seconds = duration_until_game.num_seconds();
     if duration_until_game_seconds < 0 {
-        TWO_HOURS_IN_SECONDS
-    } else if duration_until_game_seconds > TWENTY_MINUTES_IN_SECONDS {
-        (duration_until_game_seconds - TWENTY_MINUTES_IN_SECONDS).min(TWO_HOURS_IN_SECONDS)
+        policy.max_secs
+    } else if duration_until_game_seconds > policy.wake_before_game_secs {
+        (duration_until_game_seconds - policy.wake_before_game_secs).min(policy.max_secs)
     } else {
-        ONE_MINUTE_IN_SECONDS
+        policy.pregame_secs
     }
 }
 
@@ -312,388 +641,995 @@ impl PlayoffGameNumber {
     }
 }
 
+/// The two type digits of an NHL game id, which is laid out as `SSSSTTNNNN`:
+/// season, game type, game number.
 #[derive(Debug, PartialEq)]
 enum GameType {
-    Preseason(usize),
-    Regular(usize),
+    Preseason,
+    Regular,
     Playoff(PlayoffGameNumber),
+    AllStar,
+    International,
+    Other(usize),
 }
 
 impl GameType {
     fn parse(game_id: usize) -> Self {
-        let game_number = game_id % 100_000;
-        let game_type = game_number / 10_000;
-        match game_type {
-            1 => GameType::Preseason(game_number),
-            3 => GameType::Playoff(PlayoffGameNumber::parse(game_number)),
-            _ => GameType::Regular(game_number),
+        match (game_id / 10_000) % 100 {
+            1 => GameType::Preseason,
+            2 => GameType::Regular,
+            3 => GameType::Playoff(PlayoffGameNumber::parse(game_id)),
+            4 => GameType::AllStar,
+            9 | 19 => GameType::International,
+            code => GameType::Other(code),
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
 struct GameId {
+    /// The year the season starts in.
     season: usize,
     game_type: GameType,
+    /// The game's number among games of its type that season.
+    number: usize,
 }
 
 fn decode_game_id(game_id: usize) -> Option<GameId> {
     let season = game_id / 1_000_000;
+    if !(1917..=9999).contains(&season) {
+        return None;
+    }
 
     Some(GameId {
         season,
         game_type: GameType::parse(game_id),
+        number: game_id % 10_000,
     })
 }
 
-fn formatted_next_up(team: &str, game_id: usize) -> String {
+fn formatted_next_up(team: &str, game: &GameInfo) -> String {
     let default_value = format!("{} Next Up", team);
-    if let Some(game_id) = decode_game_id(game_id) {
-        match game_id.game_type {
-            GameType::Playoff(pgn) => format!("{} - Game {}", team, pgn.game),
-            _ => default_value,
+    let game_type = match decode_game_id(game.game_id) {
+        Some(game_id) => game_id.game_type,
+        None => return default_value,
+    };
+    match (game_type, &game.series, &game.special_event) {
+        (GameType::Playoff(pgn), Some(series), _) => {
+            format!("{} - Game {}", series.round_name(), pgn.game)
         }
-    } else {
-        default_value
+        (GameType::Playoff(pgn), None, _) => format!("{} - Game {}", team, pgn.game),
+        (GameType::AllStar, _, _) => "All-Star".to_string(),
+        (_, _, Some(special_event)) => special_event.clone(),
+        (GameType::Preseason, _, _) => format!("{} Preseason", team),
+        (GameType::International, _, _) => format!("{} International", team),
+        _ => default_value,
+    }
+}
+
+/// The state of the playoff series going into `game`.
+fn series_line(game: &GameInfo) -> Option<String> {
+    let series = game.series.as_ref()?;
+    match decode_game_id(game.game_id)?.game_type {
+        GameType::Playoff(pgn) => Some(series.status_for_game(pgn.game)),
+        _ => None,
     }
 }
 
 impl Default for NextUp {
     fn default() -> Self {
-        let utc_now: DateTime<Utc> = Utc::now();
-        let pacific_now = utc_now.with_timezone(&Pacific);
-        let sleep = 900;
+        Self::empty(&Utc::now(), DEFAULT_TIME_ZONE)
+    }
+}
+
+impl NextUp {
+    fn empty(utc_now: &DateTime<Utc>, tz: Tz) -> Self {
+        let local_now = utc_now.with_timezone(&tz);
         Self {
             bottom: "".to_string(),
             middle: "No Games".to_string(),
             top: "No Team Name".to_string(),
-            time: format_date_time(&pacific_now),
-            sleep,
-            date: utc_now,
+            time: format_date_time(&local_now),
+            sleep: 0,
+            date: *utc_now,
+            home_score: None,
+            away_score: None,
+            ticket: None,
+            state: NextUpState::NoGames,
+            source: None,
+            time_tbd: false,
+            status: FeedStatus::Ok,
+            stale: false,
+            error: None,
+            series: None,
+            records: None,
+            alert: None,
+            scoreboard: None,
         }
     }
-}
 
-impl NextUp {
+    /// Shown when upstream data couldn't be fetched or read, so the screen
+    /// doesn't look like the season is over.
+    fn unavailable(
+        top: &str,
+        error: &str,
+        policy: &SleepPolicy,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Self {
+        Self {
+            top: top.to_string(),
+            middle: "Schedule unavailable".to_string(),
+            bottom: format!("Retrying in {} min", policy.retry_secs / 60),
+            status: FeedStatus::Error,
+            error: Some(error.to_string()),
+            ..Self::empty(utc_now, tz)
+        }
+    }
+
+    /// An earlier good `NextUp` served in place of fresh data, with the
+    /// relative date worked out again for `utc_now`.
+    fn stale(self, error: &str, utc_now: &DateTime<Utc>, tz: Tz) -> Self {
+        let local_now = utc_now.with_timezone(&tz);
+        let date_local = self.date.with_timezone(&tz);
+        // Upcoming games and events show their date relative to today.
+        let relative_date = matches!(
+            self.source,
+            Some(SourceKind::Nhl | SourceKind::Ahl | SourceKind::Events)
+        );
+        let bottom = if relative_date && self.state == NextUpState::Upcoming && self.date > *utc_now
+        {
+            format_game_time_relative(&date_local, &local_now, self.time_tbd)
+        } else {
+            self.bottom.clone()
+        };
+        Self {
+            bottom,
+            time: format_date_time(&local_now),
+            status: FeedStatus::Degraded,
+            stale: true,
+            error: Some(error.to_string()),
+            // The goal was announced when this was fresh.
+            alert: None,
+            ..self
+        }
+    }
+
+    /// Mark as built from partial or fallback data.
+    fn degraded(self, error: &str) -> Self {
+        Self {
+            status: FeedStatus::Degraded,
+            error: Some(error.to_string()),
+            ..self
+        }
+    }
+
+    /// Set how long the device sleeps: until shortly before the game or
+    /// event, and no longer than `retry_secs` while upstream is failing.
+    fn with_sleep(self, policy: &SleepPolicy, utc_now: &DateTime<Utc>) -> Self {
+        let sleep = match self.state {
+            NextUpState::NoGames => policy.no_games_secs,
+            NextUpState::Standings => policy.standings_secs,
+            NextUpState::Upcoming | NextUpState::Live | NextUpState::Final => {
+                sleep_time(policy, &self.date, utc_now)
+            }
+        };
+        let sleep = match self.status {
+            FeedStatus::Ok => sleep,
+            FeedStatus::Degraded => sleep.min(policy.retry_secs),
+            FeedStatus::Error => policy.retry_secs,
+        };
+        Self { sleep, ..self }
+    }
+
     fn new(
         nickname: &str,
         linescore_response_string: &str,
         next_response_string: &str,
         team_id: usize,
         utc_now: &DateTime<Utc>,
-    ) -> Result<Self, Error> {
-        let pacific_now = utc_now.with_timezone(&Pacific);
-
+        tz: Tz,
+    ) -> Result<Self, EngineError> {
         let line_schedule: NextGameSchedule =
-            serde_json::from_str(&linescore_response_string).context("line_schedule")?;
-
-        let next = if line_schedule.game_today(utc_now) {
-            let first = String::from("1st");
-            let no_time = String::from("00:00");
-            let game_date = &line_schedule.dates[0];
-            let game = &game_date.games[0];
-            let linescore = game.linescore.as_ref().expect("linescore");
-            let game_date_pacific = game.game_date.with_timezone(&Pacific);
-            let opponent_name = opponent_name(&game.teams, team_id);
-            let bottom;
-            let sleep = sleep_time(&game_date_pacific, &pacific_now);
-            let top = if game.status.is_preview() {
-                if game.status.is_pregame() {
-                    bottom = "Live".to_string();
-                    "Pregame".to_string()
-                } else {
-                    bottom = format!("Today @ {}", format_date_time(&game_date_pacific));
-                    formatted_next_up(nickname, game.game_pk)
+            from_json("linescore schedule", linescore_response_string)?;
+
+        let next = if let Some(game) = line_schedule.todays_game(utc_now, tz) {
+            Self::from_todays_game(nickname, &game.game_info(), team_id, utc_now, tz)
+        } else {
+            let schedule: Response = from_json("next schedule", next_response_string)?;
+            let team = schedule
+                .teams
+                .first()
+                .ok_or_else(|| EngineError::data_shape("next schedule", "no teams"))?;
+            match team
+                .next_game_schedule
+                .as_ref()
+                .and_then(|next_game_schedule| next_game_schedule.first_game())
+            {
+                Some(game) => {
+                    Self::from_next_game(nickname, &game.game_info(), team_id, utc_now, tz)
                 }
-            } else if game.status.is_live() {
-                bottom = "Live".to_string();
-                let intermission_info = linescore
-                    .intermission_info
-                    .as_ref()
-                    .expect("intermission_info");
-                if intermission_info.in_intermission {
-                    let intermission_time_left = chrono::Duration::seconds(
-                        intermission_info.intermission_time_remaining as i64,
-                    );
+                None => Self::no_games(nickname, utc_now, tz),
+            }
+        };
+        Ok(next)
+    }
+
+    fn from_todays_game(
+        nickname: &str,
+        game: &GameInfo,
+        team_id: usize,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Self {
+        let local_now = utc_now.with_timezone(&tz);
+        let game_date_local = game.date.with_timezone(&tz);
+        let (top, bottom) = match &game.state {
+            GameState::Scheduled => (
+                formatted_next_up(nickname, game),
+                format!("Today @ {}", format_date_time(&game_date_local)),
+            ),
+            GameState::Pregame => ("Pregame".to_string(), "Live".to_string()),
+            GameState::Live(live) => {
+                let first = String::from("1st");
+                let no_time = String::from("00:00");
+                let period = live.period_ordinal.as_ref().unwrap_or(&first);
+                let top = if let Some(intermission_remaining) = live.intermission_remaining {
+                    let intermission_time_left =
+                        chrono::Duration::seconds(intermission_remaining as i64);
                     let m = intermission_time_left.num_minutes();
                     let s = intermission_time_left.num_seconds() - m * 60;
-                    format!(
-                        "{} int|{}:{:02}",
-                        linescore.current_period_ordinal.as_ref().unwrap_or(&first),
-                        m,
-                        s
-                    )
+                    format!("{} int|{}:{:02}", period, m, s)
                 } else {
                     format!(
                         "{} | {}",
-                        linescore.current_period_ordinal.as_ref().unwrap_or(&first),
-                        linescore
-                            .current_period_time_remaining
-                            .as_ref()
-                            .unwrap_or(&no_time)
+                        period,
+                        live.time_remaining.as_ref().unwrap_or(&no_time)
                     )
-                }
-            } else {
-                bottom = "".to_string();
-                "Final".to_string()
-            };
-            NextUp {
-                bottom,
-                middle: opponent_name,
-                top: top.into(),
-                time: format_date_time(&pacific_now),
-                sleep,
-                date: game.game_date,
-            }
-        } else {
-            let schedule: Response =
-                serde_json::from_str(&next_response_string).context("next schedule")?;
-            let team = &schedule.teams[0];
-            let next_game_schedule = &team.next_game_schedule;
-            let pacific_now = utc_now.with_timezone(&Pacific);
-            if let Some(next_game_schedule) = next_game_schedule {
-                let game_date = &next_game_schedule.dates[0];
-                let game = &game_date.games[0];
-
-                let game_date_pacific = game.game_date.with_timezone(&Pacific);
-
-                let sleep = sleep_time(&game_date_pacific, &pacific_now);
-
-                let opponent_name = opponent_name(&game.teams, team_id);
-
-                let date_str = format_game_time_relative(
-                    &game_date_pacific,
-                    &pacific_now,
-                    game.status.is_tbd(),
-                );
-
-                NextUp {
-                    bottom: date_str,
-                    middle: opponent_name,
-                    top: formatted_next_up(nickname, game.game_pk),
-                    time: format_date_time(&pacific_now),
-                    sleep,
-                    date: game.game_date,
-                }
-            } else {
-                NextUp {
-                    top: format!("{} Next Up", nickname),
-                    ..NextUp::default()
-                }
+                };
+                let bottom = score_line(game, team_id).unwrap_or_else(|| "Live".to_string());
+                (top, bottom)
             }
+            GameState::Final => (
+                "Final".to_string(),
+                score_line(game, team_id).unwrap_or_default(),
+            ),
         };
-        Ok(next)
+        let (home_score, away_score) = match game.state {
+            GameState::Live(_) | GameState::Final => (game.home.score, game.away.score),
+            _ => (None, None),
+        };
+        let state = match game.state {
+            GameState::Scheduled | GameState::Pregame => NextUpState::Upcoming,
+            GameState::Live(_) => NextUpState::Live,
+            GameState::Final => NextUpState::Final,
+        };
+        NextUp {
+            bottom,
+            middle: opponent_name(game, team_id),
+            top,
+            time: format_date_time(&local_now),
+            date: game.date,
+            home_score,
+            away_score,
+            state,
+            source: Some(SourceKind::Nhl),
+            time_tbd: game.time_tbd,
+            series: series_line(game),
+            scoreboard: Some(Scoreboard::from_game(game)),
+            ..NextUp::empty(utc_now, tz)
+        }
     }
 
-    fn new_event(utc_now: &DateTime<Utc>, events_text: &str) -> Result<Self, Error> {
-        let pacific_now = utc_now.with_timezone(&Pacific);
-        let mut events: EventList = toml::from_str(events_text).expect("events");
-        events
-            .events
-            .sort_by(|a, b| a.date.partial_cmp(&b.date).expect("partial_cmp"));
+    fn from_next_game(
+        nickname: &str,
+        game: &GameInfo,
+        team_id: usize,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Self {
+        let local_now = utc_now.with_timezone(&tz);
+        let game_date_local = game.date.with_timezone(&tz);
+        let date_str = format_game_time_relative(&game_date_local, &local_now, game.time_tbd);
+        NextUp {
+            bottom: date_str,
+            middle: opponent_name(game, team_id),
+            top: formatted_next_up(nickname, game),
+            time: format_date_time(&local_now),
+            date: game.date,
+            state: NextUpState::Upcoming,
+            source: Some(SourceKind::Nhl),
+            time_tbd: game.time_tbd,
+            series: series_line(game),
+            ..NextUp::empty(utc_now, tz)
+        }
+    }
+
+    fn no_games(nickname: &str, utc_now: &DateTime<Utc>, tz: Tz) -> Self {
+        NextUp {
+            top: format!("{} Next Up", nickname),
+            source: Some(SourceKind::Nhl),
+            ..NextUp::empty(utc_now, tz)
+        }
+    }
+
+    fn new_event(utc_now: &DateTime<Utc>, tz: Tz, events_text: &str) -> Result<Self, EngineError> {
+        let local_now = utc_now.with_timezone(&tz);
+        let mut events: EventList = from_toml("events", events_text)?;
+        events.events.sort_by_key(|event| event.date);
         let event = events.events.iter().find(|event| event.date > *utc_now);
         if let Some(event) = event {
-            let event_date_pacific = event.date.with_timezone(&Pacific);
-            let sleep = sleep_time(&event_date_pacific, &pacific_now);
-            let date_str = format_game_time_relative(&event_date_pacific, &pacific_now, false);
+            let event_date_local = event.date.with_timezone(&tz);
+            let date_str = format_game_time_relative(&event_date_local, &local_now, false);
             Ok(Self {
                 top: SHARKS_NEXT_UP.to_string(),
                 middle: event.text.clone(),
                 bottom: date_str,
-                time: format_date_time(&pacific_now),
-                sleep,
+                time: format_date_time(&local_now),
                 date: event.date,
+                state: NextUpState::Upcoming,
+                source: Some(SourceKind::Events),
+                ..Self::empty(utc_now, tz)
             })
         } else {
             Ok(Self {
                 top: SHARKS_NEXT_UP.to_string(),
-                ..Self::default()
+                source: Some(SourceKind::Events),
+                ..Self::empty(utc_now, tz)
             })
         }
     }
 
-    fn new_barracuda_event(utc_now: &DateTime<Utc>, games: Vec<AhlGame>) -> Result<Self, Error> {
-        let pacific_now = utc_now.with_timezone(&Pacific);
+    fn new_barracuda_event(utc_now: &DateTime<Utc>, tz: Tz, games: Vec<AhlGame>) -> Self {
+        let local_now = utc_now.with_timezone(&tz);
         let maybe_next_game = games.iter().find(|game| game.date > *utc_now);
         if let Some(next_game) = maybe_next_game {
-            let event_date_pacific = next_game.date.with_timezone(&Pacific);
-            let sleep = sleep_time(&event_date_pacific, &pacific_now);
-            let date_str = format_game_time_relative(&event_date_pacific, &pacific_now, false);
-            Ok(Self {
+            let event_date_local = next_game.date.with_timezone(&tz);
+            let date_str = format_game_time_relative(&event_date_local, &local_now, false);
+            // On game day, where we're sitting is more use than the header.
+            let ticket = next_game
+                .ticket
+                .clone()
+                .filter(|_| event_date_local.date() == local_now.date());
+            let seat = ticket.as_ref().and_then(|ticket| ticket.seat_description());
+            Self {
                 bottom: date_str,
-                middle: next_game.opponent_name.clone(),
-                top: CUDA_NEXT_UP.to_string(),
-                time: format_date_time(&pacific_now),
-                sleep,
+                middle: next_game.display_name(),
+                top: seat.unwrap_or_else(|| CUDA_NEXT_UP.to_string()),
+                time: format_date_time(&local_now),
                 date: next_game.date,
-            })
+                ticket,
+                state: NextUpState::Upcoming,
+                source: Some(SourceKind::Ahl),
+                ..Self::empty(utc_now, tz)
+            }
         } else {
-            Ok(Self {
+            Self {
                 top: CUDA_NEXT_UP.to_string(),
-                ..Self::default()
-            })
+                source: Some(SourceKind::Ahl),
+                ..Self::empty(utc_now, tz)
+            }
         }
     }
 }
 
-async fn get_nhl_next_up(team_id: usize) -> Result<NextUp, Error> {
-    let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or_else(|| &"Unknown");
-    let opt = Opt::from_args();
-    let utc_now: DateTime<Utc> = Utc::now();
+/// The text of `source`, a local path or an http(s) URL.
+async fn load_text(state: &AppState, provider: &str, source: &str) -> Result<String, Error> {
+    if config::is_url(source) {
+        Ok(fetch_text(state, provider, source).await?)
+    } else {
+        async_std::fs::read_to_string(source)
+            .await
+            .with_context(|| format!("reading {}", source))
+    }
+}
 
+/// The body at `url`, timed and counted under `provider`, and saved when
+/// recording.
+async fn fetch_text(state: &AppState, provider: &str, url: &str) -> Result<String, EngineError> {
+    let result = fetch_unrecorded(state, provider, url).await;
+    if let (Ok(text), Some(recorder)) = (&result, &state.recorder) {
+        if let Err(err) = recorder.record(url, text, Utc::now()) {
+            warn!("recording {} failed: {:#}", url, err);
+        }
+    }
+    result
+}
+
+/// Like `fetch_text`, but never saved to a recording.
+async fn fetch_unrecorded(
+    state: &AppState,
+    provider: &str,
+    url: &str,
+) -> Result<String, EngineError> {
+    let started = std::time::Instant::now();
+    let result = get_text(state, url).await;
+    metrics::observe_upstream(provider, result.is_ok(), started);
+    if let Some(upstream) = Upstream::for_provider(provider) {
+        let outcome = result.as_ref().map(|_| ()).map_err(|err| err.to_string());
+        state
+            .health
+            .record(upstream, outcome, started.elapsed(), Utc::now());
+    }
+    result
+}
+
+async fn get_text(state: &AppState, url: &str) -> Result<String, EngineError> {
+    if let Some(replay) = &state.replay {
+        return replay
+            .response(url)
+            .map_err(|err| EngineError::upstream(url, format!("{:#}", err)));
+    }
+    let mut response = state
+        .client
+        .get(url)
+        .await
+        .map_err(|err| EngineError::upstream(url, err))?;
+    if !response.status().is_success() {
+        return Err(EngineError::upstream(
+            url,
+            format!("HTTP {}", response.status()),
+        ));
+    }
+    response
+        .body_string()
+        .await
+        .map_err(|err| EngineError::upstream(url, err))
+}
+
+async fn get_legacy_nhl_next_up(
+    state: &AppState,
+    nickname: &str,
+    team_id: usize,
+    utc_now: &DateTime<Utc>,
+    tz: Tz,
+) -> Result<NextUp, Error> {
+    let opt = &state.opt;
+    let mut cache_fill = CacheFill::new(state);
     let next_response_string = if let Some(next) = opt.next.as_ref() {
         fs::read_to_string(next)?
     } else {
-        let mut next_response = surf::get(format!(
-            "https://statsapi.web.nhl.com/api/v1/teams/{}?expand=team.schedule.next",
-            team_id
-        ))
-        .await
-        .map_err(anyhow::Error::msg)?;
-        let next_response_string = next_response
-            .body_string()
-            .await
-            .map_err(anyhow::Error::msg)?;
-        next_response_string
+        cache_fill
+            .fetch(
+                CacheKey::new("statsapi-team", team_id),
+                &format!(
+                    "{}/teams/{}?expand=team.schedule.next",
+                    state.config.upstreams.nhl_stats, team_id
+                ),
+            )
+            .await?
     };
 
     let linescore_response_string = if let Some(line) = opt.line.as_ref() {
         fs::read_to_string(line)?
     } else {
-        let mut line_response = surf::get(format!(
-            "https://statsapi.web.nhl.com/api/v1/schedule?expand=schedule.linescore&teamId={}",
-            team_id
-        ))
-        .await
-        .map_err(anyhow::Error::msg)?;
-        let line_response_string = line_response
-            .body_string()
-            .await
-            .map_err(anyhow::Error::msg)?;
-        line_response_string
+        cache_fill
+            .fetch(
+                CacheKey::new("statsapi-schedule", team_id),
+                &format!(
+                    "{}/schedule?expand=schedule.linescore&teamId={}",
+                    state.config.upstreams.nhl_stats, team_id
+                ),
+            )
+            .await?
     };
 
-    Ok(NextUp::new(
+    let next = NextUp::new(
         nickname,
         &linescore_response_string,
         &next_response_string,
         team_id,
-        &utc_now,
-    )?)
+        utc_now,
+        tz,
+    )?;
+    cache_fill.store(&next, utc_now);
+    Ok(next)
 }
 
-async fn get_next_up(req: tide::Request<()>) -> tide::Result {
-    let opt = Opt::from_args();
-    let team_id_param = req
-        .param("team")
-        .ok()
-        .and_then(|team_id_str| team_id_str.parse::<usize>().ok());
-    let team_id = team_id_param.unwrap_or_else(|| opt.team.unwrap_or(SHARKS_ID));
-    let next = get_nhl_next_up(team_id).await.ok().unwrap_or_default();
-    let next_json = serde_json::to_string(&next)?;
-    let response = tide::Response::builder(tide::StatusCode::Ok)
-        .body(next_json)
-        .content_type(http_types::mime::JSON)
-        .build();
+/// With the web API, `--next` supplies the club schedule and `--line` the
+/// gamecenter landing for today's game.
+async fn get_web_nhl_next_up(
+    state: &AppState,
+    nickname: &str,
+    team_id: usize,
+    utc_now: &DateTime<Utc>,
+    tz: Tz,
+) -> Result<NextUp, Error> {
+    let opt = &state.opt;
+    let base = &state.config.upstreams.nhl_web;
+    let mut cache_fill = CacheFill::new(state);
+    let schedule_response_string = if let Some(next) = opt.next.as_ref() {
+        fs::read_to_string(next)?
+    } else {
+        let abbreviation = TEAM_ABBREVIATIONS
+            .get(&team_id)
+            .with_context(|| format!("no abbreviation for team {}", team_id))?;
+        cache_fill
+            .fetch(
+                CacheKey::new("club-schedule", team_id),
+                &nhl_web::club_schedule_url(base, abbreviation),
+            )
+            .await?
+    };
 
-    Ok(response)
+    let landing_response_string = if let Some(line) = opt.line.as_ref() {
+        Some(fs::read_to_string(line)?)
+    } else if let Some(game_id) = nhl_web::todays_game_id(&schedule_response_string, utc_now, tz)? {
+        Some(
+            cache_fill
+                .fetch(
+                    CacheKey::new("landing", team_id),
+                    &nhl_web::landing_url(base, game_id),
+                )
+                .await?,
+        )
+    } else {
+        None
+    };
+
+    // Series and standings only dress up the display, so a failure to fetch
+    // them degrades the response rather than failing it.
+    let mut problems = vec![];
+    let series_response_string = if let Some(series) = opt.series.as_ref() {
+        Some(fs::read_to_string(series)?)
+    } else if let Some(season) = nhl_web::playoff_season(&schedule_response_string, utc_now, tz)? {
+        match cache_fill
+            .fetch(
+                CacheKey::new("playoff-carousel", team_id),
+                &playoffs::carousel_url(base, season),
+            )
+            .await
+        {
+            Ok(text) => Some(text),
+            Err(err) => {
+                warn!("series records for team {} unavailable: {}", team_id, err);
+                problems.push(err.to_string());
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let standings_response_string = if opt.records {
+        match fetch_standings(state).await {
+            Ok(text) => Some(text),
+            Err(err) => {
+                warn!("standings for team {} unavailable: {}", team_id, err);
+                problems.push(err.to_string());
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let next = NextUp::new_web(
+        nickname,
+        &schedule_response_string,
+        landing_response_string.as_deref(),
+        nhl_web::Extras {
+            series: series_response_string.as_deref(),
+            standings: standings_response_string.as_deref(),
+        },
+        team_id,
+        utc_now,
+        tz,
+    )?;
+    // Don't keep a landing that couldn't be read.
+    if next.status == FeedStatus::Ok {
+        cache_fill.store(&next, utc_now);
+    }
+    let problems: Vec<String> = next.error.iter().cloned().chain(problems).collect();
+    Ok(if problems.is_empty() {
+        next
+    } else {
+        next.degraded(&problems.join("; "))
+    })
 }
 
-async fn redirect_root(_request: tide::Request<()>) -> tide::Result {
-    Ok(tide::Redirect::new("/next").into())
+/// The next up for `team_id` without goal alerts, for callers that track
+/// goals themselves.
+async fn fetch_nhl_next_up(
+    state: &AppState,
+    team_id: usize,
+    utc_now: &DateTime<Utc>,
+    tz: Tz,
+) -> Result<NextUp, Error> {
+    let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or(&"Unknown");
+
+    if state.opt.legacy {
+        get_legacy_nhl_next_up(state, nickname, team_id, utc_now, tz).await
+    } else {
+        get_web_nhl_next_up(state, nickname, team_id, utc_now, tz).await
+    }
 }
 
-async fn get_events(_req: tide::Request<()>) -> tide::Result {
-    let utc_now: DateTime<Utc> = Utc::now();
+async fn get_nhl_next_up(
+    state: &AppState,
+    team_id: usize,
+    utc_now: &DateTime<Utc>,
+    tz: Tz,
+) -> Result<NextUp, Error> {
+    let next = fetch_nhl_next_up(state, team_id, utc_now, tz).await?;
+    Ok(state.goals.observe(team_id, next, utc_now))
+}
 
-    let mut event_response = surf::get("https://storage.googleapis.com/magtag/events.toml")
-        .await
-        .map_err(anyhow::Error::msg)?;
+/// League standings, used for the standings page and team records. One
+/// response covers the whole league.
+async fn fetch_standings(state: &AppState) -> Result<String, EngineError> {
+    let mut cache_fill = CacheFill::new(state);
+    let standings_response_string = cache_fill
+        .fetch(
+            CacheKey::new("standings", 0),
+            &standings::standings_url(&state.config.upstreams.nhl_web),
+        )
+        .await?;
+    // Standings only change once games finish, so they keep for the off-day
+    // TTL whatever state a game is in.
+    cache_fill.store_for(state.config.cache.off_day());
+    Ok(standings_response_string)
+}
 
-    let event_response_string = event_response
-        .body_string()
-        .await
-        .map_err(anyhow::Error::msg)?;
+async fn get_standings_next_up(
+    state: &AppState,
+    team_id: usize,
+    utc_now: &DateTime<Utc>,
+    tz: Tz,
+) -> Result<NextUp, Error> {
+    let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or(&"Unknown");
+    let abbreviation = TEAM_ABBREVIATIONS
+        .get(&team_id)
+        .with_context(|| format!("no abbreviation for team {}", team_id))?;
+    let standings_response_string = fetch_standings(state).await?;
+    Ok(NextUp::new_standings(
+        nickname,
+        abbreviation,
+        &standings_response_string,
+        utc_now,
+        tz,
+    )?)
+}
 
-    let next = NextUp::new_event(&utc_now, &event_response_string)?;
+fn query_param(req: &tide::Request<AppState>, name: &str) -> Option<String> {
+    req.url()
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// The display time zone: the `tz` query parameter if present, otherwise
+/// the configured default.
+fn time_zone(req: &tide::Request<AppState>) -> tide::Result<Tz> {
+    match query_param(req, "tz") {
+        Some(tz_param) => tz_param.parse::<Tz>().map_err(|err| {
+            tide::Error::from_str(tide::StatusCode::BadRequest, format!("tz: {}", err))
+        }),
+        None => Ok(req.state().config.tz),
+    }
+}
+
+const BMP_EXTENSION: &str = ".bmp";
+
+/// Save `next` as the last good response for `key` when it is, or fall back
+/// to the saved one when `next` is an upstream failure.
+fn remember(state: &AppState, key: &str, next: NextUp, utc_now: &DateTime<Utc>, tz: Tz) -> NextUp {
+    let last_good = &state.last_good;
+    match next.status {
+        FeedStatus::Ok => {
+            if let Err(err) = last_good.save(key, &next, utc_now) {
+                warn!("saving last good {}: {:#}", key, err);
+            }
+            next
+        }
+        FeedStatus::Degraded => next,
+        FeedStatus::Error => match last_good.load(key, utc_now) {
+            Ok(Some(saved)) => {
+                info!("serving last good {}", key);
+                saved.stale(next.error.as_deref().unwrap_or_default(), utc_now, tz)
+            }
+            Ok(None) => next,
+            Err(err) => {
+                warn!("loading last good {}: {:#}", key, err);
+                next
+            }
+        },
+    }
+}
 
-    let next_json = serde_json::to_string(&next)?;
+/// "Now" for this request: `?now=` in debug builds, otherwise the next
+/// recorded moment when replaying, otherwise the clock.
+fn request_now(req: &tide::Request<AppState>) -> tide::Result<DateTime<Utc>> {
+    let state = req.state();
+    let now_param = clock::now_param(req.url()).map_err(|err| {
+        tide::Error::from_str(tide::StatusCode::BadRequest, format!("now: {:#}", err))
+    })?;
+    if now_param.is_some() && !state.now_param {
+        return Err(tide::Error::from_str(
+            tide::StatusCode::BadRequest,
+            "now: only available in debug builds",
+        ));
+    }
+    Ok(match (now_param, &state.replay) {
+        (Some(now), _) => now,
+        (None, Some(replay)) => replay.advance(),
+        (None, None) => state.clock.now(),
+    })
+}
 
-    let response = tide::Response::builder(tide::StatusCode::Ok)
-        .body(next_json)
-        .content_type(http_types::mime::JSON)
-        .build();
+/// Send `next`, with its sleep set by the configured policy, as JSON, or as
+/// a rendered e-ink bitmap when the request path ends in `.bmp`.
+fn respond(req: &tide::Request<AppState>, next: NextUp, utc_now: &DateTime<Utc>) -> tide::Result {
+    let next = next.with_sleep(&req.state().config.sleep, utc_now);
+    metrics::observe_response(metrics::route_label(req), &next);
+    let response = if req.url().path().ends_with(BMP_EXTENSION) {
+        let bmp = render::render_next_up(&next).to_bmp();
+        tide::Response::builder(tide::StatusCode::Ok)
+            .body(bmp)
+            .content_type(http_types::Mime::from("image/bmp"))
+            .build()
+    } else {
+        let next_json = serde_json::to_string(&next)?;
+        tide::Response::builder(tide::StatusCode::Ok)
+            .body(next_json)
+            .content_type(http_types::mime::JSON)
+            .build()
+    };
 
     Ok(response)
 }
 
-#[derive(Debug, Clone)]
-pub struct AhlGame {
-    date: DateTime<Utc>,
-    opponent_name: String,
+/// The `:team` path parameter, ignoring any `.bmp` extension.
+fn team_param(req: &tide::Request<AppState>) -> Option<usize> {
+    req.param("team").ok().and_then(|team_id_str| {
+        team_id_str
+            .trim_end_matches(BMP_EXTENSION)
+            .parse::<usize>()
+            .ok()
+    })
 }
 
-impl AhlGame {
-    fn new(opponent_name: &str, date: &str) -> AhlGame {
-        let date: DateTime<Utc> = DateTime::parse_from_rfc3339(date).expect("datetime").into();
-        AhlGame {
-            opponent_name: opponent_name.to_string(),
-            date,
-        }
+async fn get_next_up(req: tide::Request<AppState>) -> tide::Result {
+    if let Some(teams) = query_param(&req, "teams") {
+        return get_favorite_teams_next_up(req, &teams).await;
     }
+    let team_id = team_param(&req).unwrap_or(req.state().config.team);
+    let tz = time_zone(&req)?;
+    let utc_now = request_now(&req)?;
+    let next = match get_nhl_next_up(req.state(), team_id, &utc_now, tz).await {
+        Ok(next) => next,
+        Err(err) => {
+            warn!("team {} unavailable: {:#}", team_id, err);
+            let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or(&"Unknown");
+            NextUp::unavailable(
+                &format!("{} Next Up", nickname),
+                &format!("{:#}", err),
+                &req.state().config.sleep,
+                &utc_now,
+                tz,
+            )
+        }
+    };
+    let next = remember(
+        req.state(),
+        &format!("next/{}", team_id),
+        next,
+        &utc_now,
+        tz,
+    );
+    respond(&req, next, &utc_now)
+}
+
+/// `/next?teams=28,1,22`: whichever of the teams is playing, otherwise the
+/// one playing soonest, with ties going to the team listed first.
+async fn get_favorite_teams_next_up(req: tide::Request<AppState>, teams: &str) -> tide::Result {
+    let tz = time_zone(&req)?;
+    let utc_now = request_now(&req)?;
+    let sources = sources::parse_teams(teams).map_err(|err| {
+        tide::Error::from_str(tide::StatusCode::BadRequest, format!("teams: {:#}", err))
+    })?;
+    let next = sources::combined_next_up(req.state(), &sources, &utc_now, tz).await;
+    let names: Vec<String> = sources.iter().map(|source| source.name()).collect();
+    let next = remember(
+        req.state(),
+        &format!("teams/{}", names.join(",")),
+        next,
+        &utc_now,
+        tz,
+    );
+    respond(&req, next, &utc_now)
+}
+
+async fn get_standings(req: tide::Request<AppState>) -> tide::Result {
+    let team_id = team_param(&req).unwrap_or(req.state().config.team);
+    let tz = time_zone(&req)?;
+    let utc_now = request_now(&req)?;
+    let next = match get_standings_next_up(req.state(), team_id, &utc_now, tz).await {
+        Ok(next) => next,
+        Err(err) => {
+            warn!("standings for team {} unavailable: {:#}", team_id, err);
+            let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or(&"Unknown");
+            NextUp {
+                middle: "Standings unavailable".to_string(),
+                ..NextUp::unavailable(
+                    &format!("{} Standings", nickname),
+                    &format!("{:#}", err),
+                    &req.state().config.sleep,
+                    &utc_now,
+                    tz,
+                )
+            }
+        }
+    };
+    let next = remember(
+        req.state(),
+        &format!("standings/{}", team_id),
+        next,
+        &utc_now,
+        tz,
+    );
+    respond(&req, next, &utc_now)
 }
 
-pub fn load_games_from_list(list: &[[&str; 2]]) -> Vec<AhlGame> {
-    list.iter()
-        .map(|[opponent_name, date]| AhlGame::new(opponent_name, date))
-        .collect()
+async fn redirect_root(_request: tide::Request<AppState>) -> tide::Result {
+    Ok(tide::Redirect::new("/next").into())
 }
 
-async fn get_barracuda_next_up(_req: tide::Request<()>) -> tide::Result {
-    let games = load_games_from_list(GAMES_WITH_TICKETS);
-    let utc_now: DateTime<Utc> = Utc::now();
-    let next = NextUp::new_barracuda_event(&utc_now, games)?;
+async fn get_events(req: tide::Request<AppState>) -> tide::Result {
+    let utc_now = request_now(&req)?;
+    let tz = time_zone(&req)?;
+    let next = match EventsSource.next_up(req.state(), &utc_now, tz).await {
+        Ok(next) => next,
+        Err(err) => {
+            warn!("events unavailable: {:#}", err);
+            NextUp::unavailable(
+                SHARKS_NEXT_UP,
+                &format!("{:#}", err),
+                &req.state().config.sleep,
+                &utc_now,
+                tz,
+            )
+        }
+    };
+    let next = remember(req.state(), "events", next, &utc_now, tz);
 
-    let next_json = serde_json::to_string(&next)?;
+    respond(&req, next, &utc_now)
+}
 
-    let response = tide::Response::builder(tide::StatusCode::Ok)
-        .body(next_json)
-        .content_type(http_types::mime::JSON)
-        .build();
+/// The scraped Barracuda schedule with our tickets attached, falling back to
+/// the games we hold tickets for when the schedule page can't be fetched or
+/// parsed. Also returns what went wrong, if anything.
+async fn barracuda_games(state: &AppState) -> (Vec<AhlGame>, Option<String>) {
+    let mut problems = vec![];
+    let tickets: Vec<Ticket> = match tickets::load_tickets(state, &state.config.tickets).await {
+        Ok(tickets) => tickets
+            .into_iter()
+            .filter(|ticket| ticket.is_for(League::Ahl, ahl::BARRACUDA_TEAM))
+            .collect(),
+        Err(err) => {
+            warn!("tickets unavailable: {:#}", err);
+            problems.push(format!("tickets: {:#}", err));
+            vec![]
+        }
+    };
+    let games = match ahl::get_barracuda_schedule(state).await {
+        Ok(mut games) => {
+            ahl::attach_tickets(&mut games, &tickets);
+            games
+        }
+        Err(err) => {
+            warn!("barracuda schedule unavailable: {:#}", err);
+            problems.push(format!("schedule: {:#}", err));
+            load_games_from_list(&tickets)
+        }
+    };
+    let problem = if problems.is_empty() {
+        None
+    } else {
+        Some(problems.join("; "))
+    };
+    (games, problem)
+}
 
-    Ok(response)
+async fn get_barracuda_next_up(req: tide::Request<AppState>) -> tide::Result {
+    let utc_now = request_now(&req)?;
+    let tz = time_zone(&req)?;
+    let next = BarracudaSource.next_up(req.state(), &utc_now, tz).await?;
+    let next = remember(req.state(), "barracuda", next, &utc_now, tz);
+
+    respond(&req, next, &utc_now)
+}
+
+/// Server-sent `next_up` events for a team, one per change to what its
+/// display would show.
+async fn stream_next_up(
+    req: tide::Request<AppState>,
+    sender: tide::sse::Sender,
+) -> tide::Result<()> {
+    let team_id = team_param(&req).unwrap_or(req.state().config.team);
+    let tz = time_zone(&req)?;
+    let receiver = stream::subscribe(req.state(), stream::StreamKey { team_id, tz });
+    loop {
+        match future::timeout(stream::KEEP_ALIVE, receiver.recv()).await {
+            Ok(Ok(next)) => {
+                sender
+                    .send("next_up", serde_json::to_string(&next)?, None)
+                    .await?
+            }
+            // Dropped for falling behind.
+            Ok(Err(_)) => return Ok(()),
+            Err(_) => sender.send("keep_alive", "", None).await?,
+        }
+    }
 }
 
-async fn get_next_up_either(_req: tide::Request<()>) -> tide::Result {
-    let games = load_games_from_list(GAMES_WITH_TICKETS);
-    let utc_now: DateTime<Utc> = Utc::now();
-    let b_next = NextUp::new_barracuda_event(&utc_now, games).ok();
+async fn get_metrics(_req: tide::Request<AppState>) -> tide::Result {
+    let (content_type, body) = metrics::gather();
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(body)
+        .content_type(http_types::Mime::from(content_type.as_str()))
+        .build())
+}
 
-    let team_id = SHARKS_ID;
-    let nhl_next = get_nhl_next_up(team_id).await.ok();
+/// The process is up and serving.
+async fn get_healthz(_req: tide::Request<AppState>) -> tide::Result {
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(serde_json::json!({ "status": "ok" }))
+        .build())
+}
 
-    let next = if b_next.is_none() {
-        nhl_next.unwrap_or_default()
-    } else if nhl_next.is_none() {
-        b_next.unwrap_or_default()
+/// Each upstream's last success, failure and latency, with 503 once a
+/// required upstream has been failing for too long.
+async fn get_readyz(req: tide::Request<AppState>) -> tide::Result {
+    let ready = &req.state().config.ready;
+    let readiness =
+        req.state()
+            .health
+            .readiness(&ready.upstreams, ready.failure_threshold(), Utc::now());
+    let status = if readiness.ready {
+        tide::StatusCode::Ok
     } else {
-        let nhl_next = nhl_next.unwrap();
-        let b_next = b_next.unwrap();
-        if nhl_next.date < b_next.date {
-            nhl_next
-        } else {
-            b_next
-        }
+        tide::StatusCode::ServiceUnavailable
     };
+    Ok(tide::Response::builder(status)
+        .body(serde_json::to_value(&readiness)?)
+        .build())
+}
 
-    let next_json = serde_json::to_string(&next)?;
+async fn get_next_up_either(req: tide::Request<AppState>) -> tide::Result {
+    let utc_now = request_now(&req)?;
+    let tz = time_zone(&req)?;
+    let sources: Vec<Arc<dyn ScheduleSource>> = vec![
+        Arc::new(NhlSource { team_id: SHARKS_ID }),
+        Arc::new(BarracudaSource),
+    ];
+    let next = sources::combined_next_up(req.state(), &sources, &utc_now, tz).await;
+    let next = remember(req.state(), "either", next, &utc_now, tz);
+
+    respond(&req, next, &utc_now)
+}
 
-    let response = tide::Response::builder(tide::StatusCode::Ok)
-        .body(next_json)
-        .content_type(http_types::mime::JSON)
-        .build();
+/// `/combined?sources=nhl:28,ahl:sjb,events`: whichever source has a live
+/// game, otherwise whichever has the soonest item.
+async fn get_combined(req: tide::Request<AppState>) -> tide::Result {
+    let utc_now = request_now(&req)?;
+    let tz = time_zone(&req)?;
+    let specs =
+        query_param(&req, "sources").unwrap_or_else(|| sources::DEFAULT_SOURCES.to_string());
+    let sources = sources::parse_sources(&specs).map_err(|err| {
+        tide::Error::from_str(tide::StatusCode::BadRequest, format!("sources: {:#}", err))
+    })?;
+    let next = sources::combined_next_up(req.state(), &sources, &utc_now, tz).await;
+    let names: Vec<String> = sources.iter().map(|source| source.name()).collect();
+    let next = remember(
+        req.state(),
+        &format!("combined/{}", names.join(",")),
+        next,
+        &utc_now,
+        tz,
+    );
 
-    Ok(response)
+    respond(&req, next, &utc_now)
 }
 
 #[async_std::main]
@@ -709,29 +1645,108 @@ async fn main() -> Result<(), Error> {
 
     info!("utc {}", utc);
     info!("local {}", local);
-    info!("pacific {}", utc.with_timezone(&Pacific));
 
-    let default_port = String::from("8080");
-    let port = env::var("PORT").unwrap_or(default_port);
-    info!("starting on port {}", port);
+    let config = config::Config::load(
+        opt.config.as_deref(),
+        config::Overrides {
+            listen: opt.listen.clone(),
+            port: env::var("PORT").ok(),
+            team: opt.team,
+            tz: opt.tz,
+            events: opt.events.clone(),
+            tickets: opt.tickets.clone(),
+            state_dir: opt.state_dir.clone(),
+            max_stale_hours: opt.max_stale_hours,
+            ready_upstreams: opt.ready_upstreams.clone(),
+            ready_failure_minutes: opt.ready_failure_minutes,
+        },
+    )?;
+    info!("{} {}", config.tz, utc.with_timezone(&config.tz));
+
+    let state = AppState::new(config, opt)?;
+    let opt = &state.opt;
+    let config = &state.config;
+
+    if let Some(broker) = opt.mqtt_broker.as_ref() {
+        let (host, port) = mqtt::parse_broker(broker)?;
+        let teams = if opt.mqtt_teams.is_empty() {
+            vec![config.team]
+        } else {
+            opt.mqtt_teams.clone()
+        };
+        info!("publishing teams {:?} to mqtt {}:{}", teams, host, port);
+        mqtt::start(
+            &state,
+            mqtt::MqttConfig {
+                host,
+                port,
+                username: opt.mqtt_username.clone(),
+                password: opt.mqtt_password.clone(),
+                prefix: opt.mqtt_prefix.clone(),
+                discovery_prefix: opt.mqtt_discovery_prefix.clone(),
+                teams,
+                tz: config.tz,
+            },
+        );
+    }
+
+    // A replay only answers what devices asked for at the time, so there
+    // is nothing to probe.
+    if !state.replaying() {
+        async_std::task::spawn(health::probe_upstreams(
+            state.clone(),
+            config.ready.upstreams.clone(),
+        ));
+    }
+
+    info!("starting on {}", config.listen);
 
     tide::log::start();
 
-    let mut app = tide::new();
-    app.at("/").get(redirect_root);
-    app.at("/next").get(get_next_up);
-    app.at("/next/:team").get(get_next_up);
-    app.at("/events").get(get_events);
-    app.at("/barracuda").get(get_barracuda_next_up);
-    app.at("/either").get(get_next_up_either);
-    app.listen(format!("0.0.0.0:{}", port)).await?;
+    let listen = config.listen;
+    app(state).listen(listen).await?;
 
     Ok(())
 }
 
+/// Every route, serving from `state`.
+fn app(state: AppState) -> tide::Server<AppState> {
+    let mut app = tide::Server::with_state(state);
+    app.with(metrics::RequestMetrics);
+    at(&mut app, "/").get(redirect_root);
+    at(&mut app, "/next").get(get_next_up);
+    at(&mut app, "/next.bmp").get(get_next_up);
+    at(&mut app, "/next/:team").get(get_next_up);
+    at(&mut app, "/standings").get(get_standings);
+    at(&mut app, "/standings.bmp").get(get_standings);
+    at(&mut app, "/standings/:team").get(get_standings);
+    at(&mut app, "/stream").get(tide::sse::endpoint(stream_next_up));
+    at(&mut app, "/stream/:team").get(tide::sse::endpoint(stream_next_up));
+    at(&mut app, "/events").get(get_events);
+    at(&mut app, "/events.bmp").get(get_events);
+    at(&mut app, "/barracuda").get(get_barracuda_next_up);
+    at(&mut app, "/barracuda.bmp").get(get_barracuda_next_up);
+    at(&mut app, "/either").get(get_next_up_either);
+    at(&mut app, "/either.bmp").get(get_next_up_either);
+    at(&mut app, "/combined").get(get_combined);
+    at(&mut app, "/combined.bmp").get(get_combined);
+    at(&mut app, "/metrics").get(get_metrics);
+    at(&mut app, "/healthz").get(get_healthz);
+    at(&mut app, "/readyz").get(get_readyz);
+    app
+}
+
+/// `app.at(path)`, labelled with `path` for metrics.
+fn at<'a>(app: &'a mut tide::Server<AppState>, path: &'static str) -> tide::Route<'a, AppState> {
+    let mut route = app.at(path);
+    route.with(metrics::RouteLabel::new(path));
+    route
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::path::Path;
 
     const EMPTY_LINESCORE: &str = r#"{"totalItems": 0, "dates": []}"#;
     const NEXT_TEXT: &str = include_str!("../data/next.json");
@@ -752,10 +1767,15 @@ mod test {
     const SJS_DONE_LINESCORE_TEXT: &str = include_str!("../data/sjs_linescore_done.json");
     const SJS_DONE_TEXT: &str = include_str!("../data/sjs_done.json");
     const P1_TEXT: &str = include_str!("../data/p1_playoff.json");
+    const NJD_DURING_NO_LINESCORE_TEXT: &str = include_str!("../data/NJD_during_no_linescore.json");
+    const NJD_DURING_NO_INTERMISSION_LINESCORE_TEXT: &str =
+        include_str!("../data/NJD_during_no_intermission_linescore.json");
+    const NJD_EMPTY_DATES_LINESCORE_TEXT: &str =
+        include_str!("../data/NJD_empty_dates_linescore.json");
 
     #[test]
     fn test_next() {
-        let schedule: Response = serde_json::from_str(&NEXT_TEXT).expect("from_str");
+        let schedule: Response = serde_json::from_str(NEXT_TEXT).expect("from_str");
         assert_eq!(1, schedule.teams.len());
         let team = &schedule.teams[0];
         assert_eq!(SHARKS_ID, team.id);
@@ -774,19 +1794,21 @@ mod test {
         top: &str,
         middle: &str,
         bottom: &str,
-    ) {
-        let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or_else(|| &"Unknown");
+    ) -> NextUp {
+        let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or(&"Unknown");
         let next_up = NextUp::new(
             nickname,
             linescore_response_string,
             next_response_string,
             team_id,
             today,
+            DEFAULT_TIME_ZONE,
         )
         .expect("test_engine_with_team: next up to succeed");
         assert_eq!(next_up.top, top);
         assert_eq!(next_up.middle, middle);
         assert_eq!(next_up.bottom, bottom);
+        next_up
     }
 
     fn test_engine(
@@ -796,7 +1818,7 @@ mod test {
         top: &str,
         middle: &str,
         bottom: &str,
-    ) {
+    ) -> NextUp {
         test_engine_with_team(
             today,
             1,
@@ -805,7 +1827,7 @@ mod test {
             top,
             middle,
             bottom,
-        );
+        )
     }
 
     #[test]
@@ -861,7 +1883,7 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_PREGAME_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
@@ -869,6 +1891,7 @@ mod test {
             "@ Pittsburgh Penguins",
             "Live",
         );
+        assert_eq!(next_up.home_score, None);
     }
 
     #[test]
@@ -876,14 +1899,16 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_DURING_01_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
             "1st int|12:41",
             "@ Pittsburgh Penguins",
-            "Live",
+            "NJD 0 - 1 PIT",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(0));
     }
 
     #[test]
@@ -891,14 +1916,16 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_DURING_02_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
             "1st int|0:00",
             "@ Pittsburgh Penguins",
-            "Live",
+            "NJD 0 - 1 PIT",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(0));
     }
 
     #[test]
@@ -906,14 +1933,16 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_DURING_03_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
             "2nd | 18:32",
             "@ Pittsburgh Penguins",
-            "Live",
+            "NJD 0 - 1 PIT",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(0));
     }
 
     #[test]
@@ -921,14 +1950,16 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_DURING_04_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
             "2nd | 03:50",
             "@ Pittsburgh Penguins",
-            "Live",
+            "NJD 1 - 1 PIT",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(1));
     }
 
     #[test]
@@ -936,14 +1967,16 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_DURING_05_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
             "3rd | 08:20",
             "@ Pittsburgh Penguins",
-            "Live",
+            "NJD 1 - 1 PIT",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(1));
     }
 
     #[test]
@@ -951,14 +1984,16 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_DURING_06_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
             "3rd | 00:26",
             "@ Pittsburgh Penguins",
-            "Live",
+            "NJD 1 - 1 PIT",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(1));
     }
 
     #[test]
@@ -966,14 +2001,16 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_DURING_07_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
             "OT | 02:43",
             "@ Pittsburgh Penguins",
-            "Live",
+            "NJD 1 - 1 PIT",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(1));
     }
 
     #[test]
@@ -981,14 +2018,16 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine(
+        let next_up = test_engine(
             &today,
             NJD_AFTER_LINESCORE_TEXT,
             NJD_BEFORE_TEXT,
             "Final",
             "@ Pittsburgh Penguins",
-            "",
+            "NJD 2 - 1 PIT",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(2));
     }
 
     #[test]
@@ -996,15 +2035,17 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-03-29T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine_with_team(
+        let next_up = test_engine_with_team(
             &today,
             28,
             SJS_INT_LINESCORE_TEXT,
             NEXT_TEXT,
             "1st int|8:46",
             "vs Minnesota Wild",
-            "Live",
+            "SJS 1 - 2 MIN",
         );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(2));
     }
 
     #[test]
@@ -1012,15 +2053,17 @@ mod test {
         let today = chrono::DateTime::parse_from_rfc3339("2021-04-02T17:00:00Z")
             .unwrap()
             .with_timezone(&Utc);
-        test_engine_with_team(
+        let next_up = test_engine_with_team(
             &today,
             28,
             SJS_AFTER_LINESCORE_TEXT,
             SJS_AFTER_TEXT,
             "Final",
             "@ Los Angeles Kings",
-            "",
+            "SJS 3 - 0 LAK",
         );
+        assert_eq!(next_up.home_score, Some(0));
+        assert_eq!(next_up.away_score, Some(3));
     }
 
     #[test]
@@ -1039,6 +2082,36 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_njd_before_linescore_eastern() {
+        let today = chrono::DateTime::parse_from_rfc3339("2021-03-21T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let next_up = NextUp::new(
+            "Devils",
+            NJD_BEFORE_LINESCORE_TEXT,
+            NJD_BEFORE_TEXT,
+            1,
+            &today,
+            chrono_tz::America::New_York,
+        )
+        .unwrap();
+        assert_eq!(next_up.bottom, "Today @ 1:00PM");
+        assert_eq!(next_up.time, "6:00AM");
+    }
+
+    #[test]
+    fn test_events_time_zone() {
+        let today = chrono::DateTime::parse_from_rfc3339("2023-06-27T20:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let next_up = NextUp::new_event(&today, chrono_tz::America::New_York, EVENTS_TEXT).unwrap();
+        assert_eq!(next_up.middle, "NHL Draft Round 1");
+        assert_eq!(next_up.bottom, "Jun 28 @ 7:00PM");
+        let next_up = NextUp::new_event(&today, DEFAULT_TIME_ZONE, EVENTS_TEXT).unwrap();
+        assert_eq!(next_up.bottom, "Jun 28 @ 4:00PM");
+    }
+
     #[test]
     fn test_playoff_game_id() {
         const EDM_FIRST_FIRST: usize = 2020030181;
@@ -1051,6 +2124,84 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_preseason_game_id() {
+        let game_id = decode_game_id(2023010010).unwrap();
+        assert_eq!(game_id.season, 2023);
+        assert_eq!(game_id.game_type, GameType::Preseason);
+        assert_eq!(game_id.number, 10);
+    }
+
+    #[test]
+    fn test_regular_game_id() {
+        let game_id = decode_game_id(2020020493).unwrap();
+        assert_eq!(game_id.season, 2020);
+        assert_eq!(game_id.game_type, GameType::Regular);
+        assert_eq!(game_id.number, 493);
+    }
+
+    #[test]
+    fn test_all_star_game_id() {
+        let game_id = decode_game_id(2023040001).unwrap();
+        assert_eq!(game_id.game_type, GameType::AllStar);
+        assert_eq!(game_id.number, 1);
+    }
+
+    #[test]
+    fn test_international_game_id() {
+        assert_eq!(
+            decode_game_id(2024090003).unwrap().game_type,
+            GameType::International
+        );
+        let game_id = decode_game_id(2016190007).unwrap();
+        assert_eq!(game_id.season, 2016);
+        assert_eq!(game_id.game_type, GameType::International);
+        assert_eq!(game_id.number, 7);
+    }
+
+    #[test]
+    fn test_other_game_id() {
+        assert_eq!(
+            decode_game_id(2023060001).unwrap().game_type,
+            GameType::Other(6)
+        );
+        assert_eq!(decode_game_id(493), None);
+    }
+
+    fn game_info(game_id: usize, special_event: Option<&str>) -> GameInfo {
+        let team = |id: usize, name: &str, abbreviation: &str| GameTeam {
+            id,
+            name: name.to_string(),
+            abbreviation: abbreviation.to_string(),
+            score: None,
+        };
+        GameInfo {
+            game_id,
+            date: Utc::now(),
+            home: team(1, "New Jersey Devils", "NJD"),
+            away: team(4, "Philadelphia Flyers", "PHI"),
+            state: GameState::Scheduled,
+            time_tbd: false,
+            series: None,
+            special_event: special_event.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_formatted_next_up() {
+        let top = |game_id, special_event| {
+            formatted_next_up("Devils", &game_info(game_id, special_event))
+        };
+        assert_eq!(top(2023010010, None), "Devils Preseason");
+        assert_eq!(top(2023020868, None), "Devils Next Up");
+        assert_eq!(top(2023020868, Some("Stadium Series")), "Stadium Series");
+        assert_eq!(top(2023030111, None), "Devils - Game 1");
+        assert_eq!(top(2023040001, None), "All-Star");
+        assert_eq!(top(2024090003, None), "Devils International");
+        assert_eq!(top(2023060001, None), "Devils Next Up");
+        assert_eq!(top(493, None), "Devils Next Up");
+    }
+
     #[test]
     fn test_playoff_one() {
         let today = chrono::DateTime::parse_from_rfc3339("2021-05-14T10:00:00Z")
@@ -1079,10 +2230,348 @@ mod test {
 
     #[test]
     fn test_barracuda() {
-        let games = load_games_from_list(GAMES_WITH_TICKETS);
+        let tickets = tickets::parse_tickets(include_str!("../data/tickets.toml")).unwrap();
+        let games = load_games_from_list(&tickets);
         assert_eq!(games.len(), 21);
         assert_eq!(&games[0].opponent_name, "Icehogs");
         assert_eq!(&games[2].opponent_name, "Firebirds");
         assert_eq!(&games[20].opponent_name, "Stars");
     }
+
+    #[test]
+    fn test_barracuda_schedule_next_up() {
+        let games = ahl::parse_schedule(include_str!("../data/barracuda.html")).unwrap();
+        let today = chrono::DateTime::parse_from_rfc3339("2022-10-20T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let next_up = NextUp::new_barracuda_event(&today, DEFAULT_TIME_ZONE, games);
+        assert_eq!(next_up.top, CUDA_NEXT_UP);
+        assert_eq!(next_up.middle, "vs Henderson Silver Knights");
+        assert_eq!(next_up.bottom, "Oct 22 @ 6:00PM");
+    }
+
+    #[test]
+    fn test_barracuda_game_day_seat() {
+        let mut games = ahl::parse_schedule(include_str!("../data/barracuda.html")).unwrap();
+        let tickets = tickets::parse_tickets(
+            r#"
+            [[tickets]]
+            opponent = "Silver Knights"
+            date = "2022-10-23T01:00:00Z"
+            league = "ahl"
+            team = "SJB"
+            section = "113"
+            row = "F"
+            seat = "7"
+            "#,
+        )
+        .unwrap();
+        ahl::attach_tickets(&mut games, &tickets);
+
+        let day_before = chrono::DateTime::parse_from_rfc3339("2022-10-21T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let next_up = NextUp::new_barracuda_event(&day_before, DEFAULT_TIME_ZONE, games.clone());
+        assert_eq!(next_up.top, CUDA_NEXT_UP);
+        assert!(next_up.ticket.is_none());
+
+        let game_day = chrono::DateTime::parse_from_rfc3339("2022-10-22T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let next_up = NextUp::new_barracuda_event(&game_day, DEFAULT_TIME_ZONE, games);
+        assert_eq!(next_up.top, "Sec 113 Row F Seat 7");
+        assert_eq!(next_up.middle, "vs Henderson Silver Knights");
+        assert_eq!(next_up.bottom, "Today @ 6:00PM");
+        assert_eq!(next_up.ticket.unwrap().row.as_deref(), Some("F"));
+    }
+
+    fn njd_game_time() -> DateTime<Utc> {
+        chrono::DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_live_without_linescore() {
+        test_engine(
+            &njd_game_time(),
+            NJD_DURING_NO_LINESCORE_TEXT,
+            NJD_BEFORE_TEXT,
+            "1st | 00:00",
+            "@ Pittsburgh Penguins",
+            "NJD 0 - 1 PIT",
+        );
+    }
+
+    #[test]
+    fn test_live_without_intermission_info() {
+        test_engine(
+            &njd_game_time(),
+            NJD_DURING_NO_INTERMISSION_LINESCORE_TEXT,
+            NJD_BEFORE_TEXT,
+            "2nd | 18:32",
+            "@ Pittsburgh Penguins",
+            "NJD 0 - 1 PIT",
+        );
+    }
+
+    #[test]
+    fn test_linescore_without_dates() {
+        test_engine(
+            &njd_game_time(),
+            NJD_EMPTY_DATES_LINESCORE_TEXT,
+            NJD_BEFORE_TEXT,
+            "Devils Next Up",
+            "@ Pittsburgh Penguins",
+            "Today @ 10:00AM",
+        );
+    }
+
+    #[test]
+    fn test_truncated_linescore() {
+        let truncated = &NJD_DURING_03_LINESCORE_TEXT[..NJD_DURING_03_LINESCORE_TEXT.len() / 2];
+        let err = NextUp::new(
+            "Devils",
+            truncated,
+            NJD_BEFORE_TEXT,
+            1,
+            &njd_game_time(),
+            DEFAULT_TIME_ZONE,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::Json {
+                what: "linescore schedule",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_next_schedule_without_teams() {
+        let err = NextUp::new(
+            "Devils",
+            EMPTY_LINESCORE,
+            r#"{"teams": []}"#,
+            1,
+            &njd_game_time(),
+            DEFAULT_TIME_ZONE,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::DataShape {
+                what: "next schedule",
+                ..
+            }
+        ));
+        assert_eq!(err.to_string(), "unexpected next schedule: no teams");
+    }
+
+    #[test]
+    fn test_malformed_events() {
+        let err = NextUp::new_event(
+            &njd_game_time(),
+            DEFAULT_TIME_ZONE,
+            include_str!("../data/events_malformed.toml"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, EngineError::Toml { what: "events", .. }));
+    }
+
+    #[test]
+    fn test_with_sleep() {
+        let policy = config::SleepPolicy::default();
+        let now = njd_game_time() - chrono::Duration::hours(1);
+        let upcoming = NextUp {
+            state: NextUpState::Upcoming,
+            date: njd_game_time(),
+            ..NextUp::default()
+        };
+        assert_eq!(
+            upcoming.clone().with_sleep(&policy, &now).sleep,
+            60 * 60 - policy.wake_before_game_secs
+        );
+        let degraded = upcoming.clone().degraded("partial");
+        assert_eq!(degraded.with_sleep(&policy, &now).sleep, policy.retry_secs);
+        let unavailable = NextUp {
+            status: FeedStatus::Error,
+            ..upcoming
+        };
+        assert_eq!(
+            unavailable.with_sleep(&policy, &now).sleep,
+            policy.retry_secs
+        );
+        let no_games = NextUp {
+            state: NextUpState::NoGames,
+            ..NextUp::default()
+        };
+        assert_eq!(
+            no_games.with_sleep(&policy, &now).sleep,
+            policy.no_games_secs
+        );
+        let standings = NextUp {
+            state: NextUpState::Standings,
+            ..NextUp::default()
+        };
+        assert_eq!(
+            standings.with_sleep(&policy, &now).sleep,
+            policy.standings_secs
+        );
+    }
+
+    /// A state directory of the test's own, empty to start with.
+    fn temp_state_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("magtag_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// The app as served, on `config` with its last good responses kept in
+    /// `state_dir`, and with `args` as its flags.
+    fn test_app(config: config::Config, state_dir: &Path, args: &[&str]) -> tide::Server<AppState> {
+        let config = config::Config {
+            state_dir: state_dir.to_path_buf(),
+            ..config
+        };
+        let mut flags = vec!["magtag_gateway"];
+        flags.extend_from_slice(args);
+        let mut state = AppState::new(config, Opt::from_iter(&flags)).expect("app state");
+        state.now_param = true;
+        app(state)
+    }
+
+    async fn get(app: &tide::Server<AppState>, path: &str) -> http_types::Response {
+        let url = http_types::Url::parse("http://localhost")
+            .unwrap()
+            .join(path)
+            .unwrap();
+        app.respond(http_types::Request::new(http_types::Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_app_next_up() {
+        // The configured team comes from the state rather than the command
+        // line, and `--next`/`--line` stand in for the NHL.
+        let state_dir = temp_state_dir("app_next_up");
+        let app = test_app(
+            config::Config {
+                team: 1,
+                ..config::Config::default()
+            },
+            &state_dir,
+            &[
+                "--next",
+                "data/web_NJD_schedule.json",
+                "--line",
+                "data/web_NJD_during_03_landing.json",
+            ],
+        );
+        let mut response = get(&app, "/next?now=2021-03-21T17:45:00Z").await;
+        assert_eq!(response.status(), tide::StatusCode::Ok);
+        let next: serde_json::Value = response.body_json().await.unwrap();
+        assert_eq!(next["top"], "2nd | 18:32");
+        assert_eq!(next["middle"], "@ Pittsburgh Penguins");
+        assert_eq!(next["state"], "live");
+
+        let response = get(&app, "/next.bmp?now=2021-03-21T17:45:00Z").await;
+        assert_eq!(response.content_type().unwrap().essence(), "image/bmp");
+
+        fs::remove_dir_all(&state_dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_app_standings_default_team() {
+        // Nothing listens on the discard port, so the fetch fails at once
+        // and the error screen names the team that was asked about.
+        let state_dir = temp_state_dir("app_standings_default_team");
+        let app = test_app(
+            config::Config {
+                team: 1,
+                upstreams: config::UpstreamUrls {
+                    nhl_web: "http://127.0.0.1:9".to_string(),
+                    ..config::UpstreamUrls::default()
+                },
+                ..config::Config::default()
+            },
+            &state_dir,
+            &[],
+        );
+        let mut response = get(&app, "/standings").await;
+        let next: serde_json::Value = response.body_json().await.unwrap();
+        assert_eq!(next["top"], "Devils Standings");
+        assert_eq!(next["status"], "error");
+
+        let _ = fs::remove_dir_all(&state_dir);
+    }
+
+    #[async_std::test]
+    async fn test_probes_not_recorded() {
+        use async_std::{io::prelude::*, net::TcpListener};
+
+        // A one-page upstream on a port of its own.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/standings", listener.local_addr().unwrap());
+        async_std::task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}")
+                    .await;
+            }
+        });
+
+        let record_dir = temp_state_dir("probes_not_recorded");
+        let state = AppState::new(
+            config::Config::default(),
+            Opt::from_iter(&["magtag_gateway", "--record", record_dir.to_str().unwrap()]),
+        )
+        .unwrap();
+        assert_eq!(
+            fetch_unrecorded(&state, "standings", &url).await.unwrap(),
+            "{}"
+        );
+        assert_eq!(fs::read_dir(&record_dir).unwrap().count(), 0);
+        assert_eq!(fetch_text(&state, "standings", &url).await.unwrap(), "{}");
+        assert_eq!(fs::read_dir(&record_dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&record_dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_app_now_param_disabled() {
+        let mut state = AppState::test();
+        state.now_param = false;
+        let app = app(state);
+        assert_eq!(
+            get(&app, "/next?now=2021-03-21T17:45:00Z").await.status(),
+            tide::StatusCode::BadRequest
+        );
+    }
+
+    #[async_std::test]
+    async fn test_app_requests() {
+        let state_dir = temp_state_dir("app_requests");
+        let app = test_app(config::Config::default(), &state_dir, &[]);
+        assert_eq!(get(&app, "/healthz").await.status(), tide::StatusCode::Ok);
+        assert_eq!(get(&app, "/readyz").await.status(), tide::StatusCode::Ok);
+        assert_eq!(
+            get(&app, "/combined?sources=mlb:1").await.status(),
+            tide::StatusCode::BadRequest
+        );
+        assert_eq!(
+            get(&app, "/next?tz=Mars/Olympus").await.status(),
+            tide::StatusCode::BadRequest
+        );
+        assert_eq!(
+            get(&app, "/next?teams=28,99").await.status(),
+            tide::StatusCode::BadRequest
+        );
+
+        let _ = fs::remove_dir_all(&state_dir);
+    }
 }
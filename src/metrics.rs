@@ -0,0 +1,224 @@
+//! Prometheus metrics, served at `/metrics`: requests per route, upstream
+//! fetches per provider, response cache lookups and fallback responses.
+
+use crate::{FeedStatus, NextUp};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
+    TextEncoder,
+};
+use std::time::Instant;
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "magtag_http_requests_total",
+        "Requests served, by route and status code",
+        &["route", "status"]
+    )
+    .expect("http requests metric")
+});
+
+static HTTP_REQUEST_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "magtag_http_request_duration_seconds",
+        "Time to serve a request, by route",
+        &["route"]
+    )
+    .expect("http request duration metric")
+});
+
+static UPSTREAM_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "magtag_upstream_fetch_duration_seconds",
+        "Time to fetch from an upstream provider, successful or not",
+        &["provider"]
+    )
+    .expect("upstream duration metric")
+});
+
+static UPSTREAM_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "magtag_upstream_failures_total",
+        "Failed fetches from an upstream provider",
+        &["provider"]
+    )
+    .expect("upstream failures metric")
+});
+
+static CACHE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "magtag_cache_lookups_total",
+        "Response cache lookups, by endpoint and hit or miss",
+        &["endpoint", "result"]
+    )
+    .expect("cache lookups metric")
+});
+
+static FALLBACKS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "magtag_fallback_responses_total",
+        "Responses served without fresh data: a saved response (stale) or \
+         an unavailable placeholder",
+        &["route", "reason"]
+    )
+    .expect("fallbacks metric")
+});
+
+/// The pattern of the route that served a request, e.g. `/next/:team`, with
+/// the `.bmp` extension folded in so labels stay few. Tide doesn't say which
+/// route matched, so each one is registered with this as route middleware.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteLabel(&'static str);
+
+impl RouteLabel {
+    pub fn new(pattern: &'static str) -> Self {
+        Self(pattern.trim_end_matches(".bmp"))
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for RouteLabel {
+    async fn handle(
+        &self,
+        mut req: tide::Request<State>,
+        next: tide::Next<'_, State>,
+    ) -> tide::Result {
+        req.set_ext(*self);
+        let mut response = next.run(req).await;
+        response.insert_ext(*self);
+        Ok(response)
+    }
+}
+
+/// The route `req` was served by, once its `RouteLabel` has run.
+pub fn route_label<State>(req: &tide::Request<State>) -> &'static str {
+    req.ext::<RouteLabel>().map_or(UNMATCHED, |label| label.0)
+}
+
+/// Shared by every path that matched no route, so scanners can't add labels
+/// without limit.
+const UNMATCHED: &str = "unmatched";
+
+pub fn observe_request(route: &str, status: u16, started: Instant) {
+    HTTP_REQUESTS
+        .with_label_values(&[route, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_SECONDS
+        .with_label_values(&[route])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+pub fn observe_upstream(provider: &str, succeeded: bool, started: Instant) {
+    UPSTREAM_SECONDS
+        .with_label_values(&[provider])
+        .observe(started.elapsed().as_secs_f64());
+    if !succeeded {
+        UPSTREAM_FAILURES.with_label_values(&[provider]).inc();
+    }
+}
+
+pub fn observe_cache_lookup(endpoint: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&[endpoint, result]).inc();
+}
+
+/// Count `next` if it is served in place of fresh data.
+pub fn observe_response(route: &str, next: &NextUp) {
+    let reason = if next.stale {
+        "stale"
+    } else if next.status == FeedStatus::Error {
+        "unavailable"
+    } else {
+        return;
+    };
+    FALLBACKS.with_label_values(&[route, reason]).inc();
+}
+
+/// Records every request's route, status and duration.
+pub struct RequestMetrics;
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for RequestMetrics {
+    async fn handle(&self, req: tide::Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        let started = Instant::now();
+        let response = next.run(req).await;
+        let route = response
+            .ext::<RouteLabel>()
+            .map_or(UNMATCHED, |label| label.0);
+        observe_request(route, response.status().into(), started);
+        Ok(response)
+    }
+}
+
+/// Everything registered, in the Prometheus text format.
+pub fn gather() -> (String, String) {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    // Encoding to memory only fails on malformed metrics.
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("encoding metrics");
+    (
+        encoder.format_type().to_string(),
+        String::from_utf8(buffer).expect("metrics are utf-8"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_route_label() {
+        let mut app = tide::new();
+        app.with(RequestMetrics);
+        app.at("/test/:team")
+            .with(RouteLabel::new("/test/:team"))
+            .get(|req: tide::Request<()>| async move { Ok(route_label(&req)) });
+        app.at("/test.bmp")
+            .with(RouteLabel::new("/test.bmp"))
+            .get(|req: tide::Request<()>| async move { Ok(route_label(&req)) });
+        let get = |path: &str| {
+            let url = tide::http::Url::parse("http://localhost")
+                .unwrap()
+                .join(path)
+                .unwrap();
+            app.respond(tide::http::Request::new(tide::http::Method::Get, url))
+        };
+
+        let mut response: tide::http::Response = get("/test/anything").await.unwrap();
+        assert_eq!(response.body_string().await.unwrap(), "/test/:team");
+        let mut response: tide::http::Response = get("/test.bmp").await.unwrap();
+        assert_eq!(response.body_string().await.unwrap(), "/test");
+        get("/test/28").await.unwrap();
+        get("/nothing/here").await.unwrap();
+
+        let (_, text) = gather();
+        assert!(text.contains("magtag_http_requests_total{route=\"/test/:team\",status=\"200\"} 2"));
+        assert!(text.contains("magtag_http_requests_total{route=\"unmatched\",status=\"404\"}"));
+    }
+
+    #[test]
+    fn test_gather() {
+        observe_upstream("test-provider", false, Instant::now());
+        observe_cache_lookup("test-endpoint", true);
+        observe_response(
+            "/test",
+            &NextUp {
+                stale: true,
+                ..NextUp::default()
+            },
+        );
+        let (format_type, text) = gather();
+        assert!(format_type.starts_with("text/plain"));
+        assert!(text.contains("magtag_upstream_failures_total{provider=\"test-provider\"} 1"));
+        assert!(text
+            .contains("magtag_cache_lookups_total{endpoint=\"test-endpoint\",result=\"hit\"} 1"));
+        assert!(
+            text.contains("magtag_fallback_responses_total{reason=\"stale\",route=\"/test\"} 1")
+        );
+        assert!(text.contains(
+            "magtag_upstream_fetch_duration_seconds_count{provider=\"test-provider\"} 1"
+        ));
+    }
+}
@@ -0,0 +1,546 @@
+//! Publishes each followed team's `NextUp`, and a flatter game state for
+//! automations, to an MQTT broker, with Home Assistant discovery so the
+//! entities show up without any YAML.
+
+use crate::{
+    goals::Alert,
+    state::AppState,
+    stream::{self, StreamKey},
+    FeedStatus, NextUp, NextUpState, TEAM_NICKNAMES,
+};
+use anyhow::{Context, Error};
+use async_std::task;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use log::{info, warn};
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+const CLIENT_ID: &str = "magtag_gateway";
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const QUEUE_CAPACITY: usize = 100;
+
+const DEFAULT_PORT: u16 = 1883;
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Topics are published under `<prefix>/<team id>/`.
+    pub prefix: String,
+    pub discovery_prefix: String,
+    pub teams: Vec<usize>,
+    pub tz: Tz,
+}
+
+/// Split `--mqtt-broker` into host and port, defaulting to 1883.
+pub fn parse_broker(broker: &str) -> Result<(String, u16), Error> {
+    match broker.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .with_context(|| format!("mqtt broker port in {}", broker))?;
+            Ok((host.to_string(), port))
+        }
+        None => Ok((broker.to_string(), DEFAULT_PORT)),
+    }
+}
+
+impl MqttConfig {
+    fn availability_topic(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    fn state_topic(&self, team_id: usize) -> String {
+        format!("{}/{}/state", self.prefix, team_id)
+    }
+
+    fn next_up_topic(&self, team_id: usize) -> String {
+        format!("{}/{}/next_up", self.prefix, team_id)
+    }
+}
+
+/// The game as automations want it, e.g. to dim the lights when `live`
+/// turns on.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GameState {
+    pub state: NextUpState,
+    pub live: bool,
+    /// Puck drop, for an upcoming game.
+    pub date: DateTime<Utc>,
+    /// Abbreviation of the other team, known from the schedule before the
+    /// game starts.
+    pub opponent: Option<String>,
+    pub home: Option<bool>,
+    pub our_score: Option<usize>,
+    pub their_score: Option<usize>,
+    pub goal: Option<Alert>,
+    pub status: FeedStatus,
+}
+
+impl GameState {
+    pub fn new(team_id: usize, next: &NextUp) -> Self {
+        let (ours, theirs) = match &next.scoreboard {
+            Some(scoreboard) if scoreboard.home.id == team_id => {
+                (Some(&scoreboard.home), Some(&scoreboard.away))
+            }
+            Some(scoreboard) => (Some(&scoreboard.away), Some(&scoreboard.home)),
+            None => (None, None),
+        };
+        Self {
+            state: next.state,
+            live: next.state == NextUpState::Live,
+            date: next.date,
+            opponent: theirs.map(|team| team.abbreviation.clone()),
+            home: next
+                .scoreboard
+                .as_ref()
+                .map(|scoreboard| scoreboard.home.id == team_id),
+            our_score: ours.and_then(|team| team.score),
+            their_score: theirs.and_then(|team| team.score),
+            goal: next.alert.clone(),
+            status: next.status,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Device {
+    identifiers: Vec<String>,
+    name: String,
+    manufacturer: &'static str,
+}
+
+/// A Home Assistant MQTT discovery config.
+#[derive(Serialize, Debug)]
+struct Discovery {
+    name: &'static str,
+    unique_id: String,
+    state_topic: String,
+    value_template: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json_attributes_topic: Option<String>,
+    availability_topic: String,
+    device: Device,
+}
+
+struct Entity {
+    component: &'static str,
+    object_id: &'static str,
+    name: &'static str,
+    value_template: &'static str,
+    device_class: Option<&'static str>,
+    /// Whether the whole `NextUp` is attached as attributes.
+    next_up_attributes: bool,
+}
+
+const ENTITIES: &[Entity] = &[
+    Entity {
+        component: "sensor",
+        object_id: "state",
+        name: "Game state",
+        value_template: "{{ value_json.state }}",
+        device_class: None,
+        next_up_attributes: true,
+    },
+    Entity {
+        component: "binary_sensor",
+        object_id: "live",
+        name: "Game live",
+        value_template: "{{ 'ON' if value_json.live else 'OFF' }}",
+        device_class: Some("running"),
+        next_up_attributes: false,
+    },
+    Entity {
+        component: "sensor",
+        object_id: "puck_drop",
+        name: "Puck drop",
+        value_template: "{{ value_json.date }}",
+        device_class: Some("timestamp"),
+        next_up_attributes: false,
+    },
+    Entity {
+        component: "sensor",
+        object_id: "our_score",
+        name: "Score",
+        value_template:
+            "{{ value_json.our_score if value_json.our_score is not none else 'unknown' }}",
+        device_class: None,
+        next_up_attributes: false,
+    },
+    Entity {
+        component: "sensor",
+        object_id: "their_score",
+        name: "Opponent score",
+        value_template:
+            "{{ value_json.their_score if value_json.their_score is not none else 'unknown' }}",
+        device_class: None,
+        next_up_attributes: false,
+    },
+];
+
+/// Discovery topics and payloads for `team_id`'s entities, grouped as one
+/// device per team.
+fn discovery_messages(config: &MqttConfig, team_id: usize) -> Vec<(String, String)> {
+    let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or(&"Unknown");
+    let node_id = format!("magtag_{}", team_id);
+    ENTITIES
+        .iter()
+        .map(|entity| {
+            let discovery = Discovery {
+                name: entity.name,
+                unique_id: format!("{}_{}", node_id, entity.object_id),
+                state_topic: config.state_topic(team_id),
+                value_template: entity.value_template,
+                device_class: entity.device_class,
+                json_attributes_topic: if entity.next_up_attributes {
+                    Some(config.next_up_topic(team_id))
+                } else {
+                    None
+                },
+                availability_topic: config.availability_topic(),
+                device: Device {
+                    identifiers: vec![node_id.clone()],
+                    name: format!("{} (MagTag gateway)", nickname),
+                    manufacturer: CLIENT_ID,
+                },
+            };
+            let topic = format!(
+                "{}/{}/{}/{}/config",
+                config.discovery_prefix, entity.component, node_id, entity.object_id
+            );
+            let payload = serde_json::to_string(&discovery).expect("discovery");
+            (topic, payload)
+        })
+        .collect()
+}
+
+fn state_messages(config: &MqttConfig, team_id: usize, next: &NextUp) -> Vec<(String, String)> {
+    let game_state = GameState::new(team_id, next);
+    vec![
+        (
+            config.next_up_topic(team_id),
+            serde_json::to_string(next).expect("next up"),
+        ),
+        (
+            config.state_topic(team_id),
+            serde_json::to_string(&game_state).expect("game state"),
+        ),
+    ]
+}
+
+/// The latest state message on each topic. Updates published while the
+/// broker is unreachable can be dropped once the client's queue fills, so
+/// these are sent again on every connect.
+#[derive(Default, Clone)]
+struct LatestState(Arc<Mutex<BTreeMap<String, String>>>);
+
+impl LatestState {
+    fn update(&self, messages: &[(String, String)]) {
+        let mut latest = self.0.lock().expect("latest mqtt state");
+        for (topic, payload) in messages {
+            latest.insert(topic.clone(), payload.clone());
+        }
+    }
+
+    fn messages(&self) -> Vec<(String, String)> {
+        let latest = self.0.lock().expect("latest mqtt state");
+        latest
+            .iter()
+            .map(|(topic, payload)| (topic.clone(), payload.clone()))
+            .collect()
+    }
+}
+
+/// Availability, discovery and the latest state of every team, for a fresh
+/// connection.
+fn connect_messages(config: &MqttConfig, latest: &LatestState) -> Vec<(String, String)> {
+    let mut messages = vec![(config.availability_topic(), ONLINE.to_string())];
+    for &team_id in &config.teams {
+        messages.extend(discovery_messages(config, team_id));
+    }
+    messages.extend(latest.messages());
+    messages
+}
+
+/// Everything is retained, so dashboards have the latest state as soon as
+/// they subscribe.
+fn publish(client: &Client, (topic, payload): (String, String)) {
+    if let Err(err) = client.try_publish(topic.as_str(), QoS::AtLeastOnce, true, payload) {
+        warn!("mqtt publish to {}: {}", topic, err);
+    }
+}
+
+/// Run rumqttc's event loop, which also reconnects, on its own thread. On
+/// every connect the broker may have forgotten or missed what was sent, so
+/// `connect_messages` go out again, from another thread that can wait for
+/// room in the queue this loop drains.
+fn spawn_connection(
+    client: Client,
+    mut connection: Connection,
+    config: MqttConfig,
+    latest: LatestState,
+) {
+    thread::spawn(move || {
+        for notification in connection.iter() {
+            match notification {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("mqtt connected to {}:{}", config.host, config.port);
+                    let messages = connect_messages(&config, &latest);
+                    let client = client.clone();
+                    thread::spawn(move || {
+                        for (topic, payload) in messages {
+                            if let Err(err) =
+                                client.publish(topic.as_str(), QoS::AtLeastOnce, true, payload)
+                            {
+                                warn!("mqtt publish to {}: {}", topic, err);
+                            }
+                        }
+                    });
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    warn!("mqtt {}:{}: {}", config.host, config.port, err);
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            }
+        }
+    });
+}
+
+/// Connect to the broker and publish every configured team's changes,
+/// using the same shared pollers as `/stream/:team`.
+pub fn start(state: &AppState, config: MqttConfig) {
+    let mut options = MqttOptions::new(CLIENT_ID, config.host.as_str(), config.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(
+        config.availability_topic(),
+        OFFLINE,
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        options.set_credentials(username, password);
+    }
+    let (client, connection) = Client::new(options, QUEUE_CAPACITY);
+    let latest = LatestState::default();
+
+    for &team_id in &config.teams {
+        let receiver = stream::subscribe(
+            state,
+            StreamKey {
+                team_id,
+                tz: config.tz,
+            },
+        );
+        let client = client.clone();
+        let config = config.clone();
+        let latest = latest.clone();
+        task::spawn(async move {
+            while let Ok(next) = receiver.recv().await {
+                let messages = state_messages(&config, team_id, &next);
+                latest.update(&messages);
+                for message in messages {
+                    publish(&client, message);
+                }
+            }
+        });
+    }
+
+    spawn_connection(client, connection, config, latest);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{nhl_web::Extras, DEFAULT_TIME_ZONE};
+
+    const NJD_SCHEDULE_TEXT: &str = include_str!("../data/web_NJD_schedule.json");
+    const NJD_BEFORE_LANDING_TEXT: &str = include_str!("../data/web_NJD_before_landing.json");
+    const NJD_DURING_03_LANDING_TEXT: &str = include_str!("../data/web_NJD_during_03_landing.json");
+
+    fn config() -> MqttConfig {
+        MqttConfig {
+            host: "localhost".to_string(),
+            port: DEFAULT_PORT,
+            username: None,
+            password: None,
+            prefix: "magtag".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+            teams: vec![1],
+            tz: DEFAULT_TIME_ZONE,
+        }
+    }
+
+    fn njd_next_up(landing: &str) -> NextUp {
+        let now = DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        NextUp::new_web(
+            "Devils",
+            NJD_SCHEDULE_TEXT,
+            Some(landing),
+            Extras::default(),
+            1,
+            &now,
+            DEFAULT_TIME_ZONE,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_broker() {
+        assert_eq!(
+            parse_broker("localhost").unwrap(),
+            ("localhost".to_string(), 1883)
+        );
+        assert_eq!(
+            parse_broker("mqtt.lan:8883").unwrap(),
+            ("mqtt.lan".to_string(), 8883)
+        );
+        assert!(parse_broker("mqtt.lan:port").is_err());
+    }
+
+    #[test]
+    fn test_live_game_state() {
+        let game_state = GameState::new(1, &njd_next_up(NJD_DURING_03_LANDING_TEXT));
+        assert_eq!(game_state.state, NextUpState::Live);
+        assert!(game_state.live);
+        assert_eq!(game_state.opponent.as_deref(), Some("PIT"));
+        assert_eq!(game_state.home, Some(false));
+        assert_eq!(game_state.our_score, Some(0));
+        assert_eq!(game_state.their_score, Some(1));
+    }
+
+    #[test]
+    fn test_upcoming_game_state() {
+        let next = njd_next_up(NJD_BEFORE_LANDING_TEXT);
+        let game_state = GameState::new(1, &next);
+        assert!(!game_state.live);
+        assert_eq!(game_state.date, next.date);
+        // The opponent is known before the game, the score isn't.
+        assert_eq!(game_state.opponent.as_deref(), Some("PIT"));
+        assert_eq!(game_state.home, Some(false));
+        assert_eq!(game_state.our_score, None);
+
+        let messages = state_messages(&config(), 1, &next);
+        let topics: Vec<&str> = messages.iter().map(|(topic, _)| topic.as_str()).collect();
+        assert_eq!(topics, ["magtag/1/next_up", "magtag/1/state"]);
+        let state: serde_json::Value = serde_json::from_str(&messages[1].1).unwrap();
+        assert_eq!(state["live"], false);
+        assert_eq!(state["status"], "ok");
+    }
+
+    #[test]
+    fn test_discovery() {
+        let messages = discovery_messages(&config(), 1);
+        assert_eq!(messages.len(), ENTITIES.len());
+        let (topic, payload) = &messages[1];
+        assert_eq!(topic, "homeassistant/binary_sensor/magtag_1/live/config");
+        let discovery: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(discovery["unique_id"], "magtag_1_live");
+        assert_eq!(discovery["state_topic"], "magtag/1/state");
+        assert_eq!(discovery["availability_topic"], "magtag/status");
+        assert_eq!(discovery["device"]["name"], "Devils (MagTag gateway)");
+        assert!(discovery.get("json_attributes_topic").is_none());
+
+        let discovery: serde_json::Value = serde_json::from_str(&messages[0].1).unwrap();
+        assert_eq!(discovery["json_attributes_topic"], "magtag/1/next_up");
+    }
+
+    #[test]
+    fn test_connect_republishes_latest_state() {
+        let config = config();
+        let latest = LatestState::default();
+        latest.update(&state_messages(
+            &config,
+            1,
+            &njd_next_up(NJD_BEFORE_LANDING_TEXT),
+        ));
+        latest.update(&state_messages(
+            &config,
+            1,
+            &njd_next_up(NJD_DURING_03_LANDING_TEXT),
+        ));
+
+        let messages = connect_messages(&config, &latest);
+        assert_eq!(
+            messages[0],
+            ("magtag/status".to_string(), ONLINE.to_string())
+        );
+        assert_eq!(messages.len(), 1 + ENTITIES.len() + 2);
+        // Only the latest state is kept for each topic.
+        let (topic, payload) = messages.last().unwrap();
+        assert_eq!(topic, "magtag/1/state");
+        let state: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(state["live"], true);
+    }
+
+    /// Needs a broker, e.g. `mosquitto -p 1883`; run with
+    /// `MAGTAG_TEST_MQTT_BROKER=localhost:1883 cargo test -- --ignored mqtt`.
+    #[test]
+    #[ignore]
+    fn test_local_broker() {
+        let broker =
+            std::env::var("MAGTAG_TEST_MQTT_BROKER").unwrap_or_else(|_| "localhost".to_string());
+        let (host, port) = parse_broker(&broker).unwrap();
+        let config = MqttConfig {
+            host: host.clone(),
+            port,
+            prefix: "magtag_test".to_string(),
+            discovery_prefix: "magtag_test_discovery".to_string(),
+            ..config()
+        };
+        let latest = LatestState::default();
+        latest.update(&state_messages(
+            &config,
+            1,
+            &njd_next_up(NJD_DURING_03_LANDING_TEXT),
+        ));
+        let (client, connection) = Client::new(
+            MqttOptions::new("magtag_gateway_test", host.as_str(), port),
+            QUEUE_CAPACITY,
+        );
+        spawn_connection(client, connection, config, latest);
+
+        // Everything is retained, so a later subscriber sees it all.
+        let (subscriber, mut connection) = Client::new(
+            MqttOptions::new("magtag_gateway_test_subscriber", host.as_str(), port),
+            QUEUE_CAPACITY,
+        );
+        subscriber
+            .subscribe("magtag_test/#", QoS::AtLeastOnce)
+            .unwrap();
+        let mut received = BTreeMap::new();
+        while received.len() < 3 {
+            match connection.recv_timeout(Duration::from_secs(10)) {
+                Ok(Ok(Event::Incoming(Packet::Publish(publish)))) => {
+                    let payload = String::from_utf8(publish.payload.to_vec()).unwrap();
+                    received.insert(publish.topic, payload);
+                }
+                Ok(Ok(_)) => (),
+                Ok(Err(err)) => panic!("subscribing: {}", err),
+                Err(err) => panic!("waiting for retained messages: {:?}", err),
+            }
+        }
+        assert_eq!(received["magtag_test/status"], ONLINE);
+        let state: serde_json::Value =
+            serde_json::from_str(&received["magtag_test/1/state"]).unwrap();
+        assert_eq!(state["live"], true);
+        assert!(received.contains_key("magtag_test/1/next_up"));
+    }
+}
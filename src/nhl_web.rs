@@ -0,0 +1,918 @@
+//! Provider for the current NHL web API at api-web.nhle.com, which replaced
+//! the statsapi.web.nhl.com endpoints.
+
+use crate::{
+    decode_game_id,
+    error::{from_json, EngineError},
+    playoffs::find_series,
+    standings::records_line,
+    GameInfo, GameState, GameTeam, GameType, LiveState, NextUp,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Default for the `nhl_web` upstream setting.
+pub const NHL_WEB_API_BASE: &str = "https://api-web.nhle.com/v1";
+
+/// URLs are built on `base`, the configured `nhl_web` upstream.
+pub fn club_schedule_url(base: &str, abbreviation: &str) -> String {
+    format!("{}/club-schedule-season/{}/now", base, abbreviation)
+}
+
+pub fn landing_url(base: &str, game_id: usize) -> String {
+    format!("{}/gamecenter/{}/landing", base, game_id)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LocalizedString {
+    pub default: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebTeam {
+    pub id: usize,
+    pub abbrev: String,
+    pub place_name: Option<LocalizedString>,
+    pub common_name: Option<LocalizedString>,
+    pub score: Option<usize>,
+}
+
+impl WebTeam {
+    fn full_name(&self) -> String {
+        match (&self.place_name, &self.common_name) {
+            (Some(place_name), Some(common_name)) => {
+                format!("{} {}", place_name.default, common_name.default)
+            }
+            (Some(place_name), None) => place_name.default.clone(),
+            (None, Some(common_name)) => common_name.default.clone(),
+            (None, None) => self.abbrev.clone(),
+        }
+    }
+}
+
+impl From<&WebTeam> for GameTeam {
+    fn from(team: &WebTeam) -> Self {
+        Self {
+            id: team.id,
+            name: team.full_name(),
+            abbreviation: team.abbrev.clone(),
+            score: team.score,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodDescriptor {
+    pub number: usize,
+    pub period_type: String,
+}
+
+impl PeriodDescriptor {
+    fn ordinal(&self) -> String {
+        match self.period_type.as_str() {
+            "OT" if self.number > 4 => format!("{}OT", self.number - 3),
+            "OT" => "OT".to_string(),
+            "SO" => "SO".to_string(),
+            _ => match self.number {
+                1 => "1st".to_string(),
+                2 => "2nd".to_string(),
+                3 => "3rd".to_string(),
+                number => format!("{}th", number),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Clock {
+    pub time_remaining: String,
+    pub seconds_remaining: usize,
+    pub in_intermission: bool,
+}
+
+/// Event names carry a year and sponsor, e.g. "2024 Navy Federal Credit
+/// Union NHL Stadium Series", which won't fit on a line.
+const SPECIAL_EVENTS: &[&str] = &[
+    "Stadium Series",
+    "Winter Classic",
+    "Heritage Classic",
+    "Global Series",
+];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpecialEvent {
+    pub name: LocalizedString,
+}
+
+impl SpecialEvent {
+    fn label(&self) -> String {
+        SPECIAL_EVENTS
+            .iter()
+            .find(|special_event| self.name.default.contains(*special_event))
+            .map(|special_event| special_event.to_string())
+            .unwrap_or_else(|| self.name.default.clone())
+    }
+}
+
+/// A game as it appears in both the club schedule and the gamecenter landing
+/// responses. Only the landing response carries the clock.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebGame {
+    pub id: usize,
+    #[serde(rename = "startTimeUTC")]
+    pub start_time_utc: DateTime<Utc>,
+    pub game_state: String,
+    pub game_schedule_state: String,
+    pub home_team: WebTeam,
+    pub away_team: WebTeam,
+    pub period_descriptor: Option<PeriodDescriptor>,
+    pub clock: Option<Clock>,
+    pub special_event: Option<SpecialEvent>,
+}
+
+impl WebGame {
+    fn is_final(&self) -> bool {
+        self.game_state == "FINAL" || self.game_state == "OFF"
+    }
+
+    fn game_state(&self) -> GameState {
+        match self.game_state.as_str() {
+            "PRE" => GameState::Pregame,
+            "LIVE" | "CRIT" => {
+                let period_ordinal = self.period_descriptor.as_ref().map(|pd| pd.ordinal());
+                let (time_remaining, intermission_remaining) = match &self.clock {
+                    Some(clock) if clock.in_intermission => (None, Some(clock.seconds_remaining)),
+                    Some(clock) => (Some(clock.time_remaining.clone()), None),
+                    None => (None, None),
+                };
+                GameState::Live(LiveState {
+                    period_ordinal,
+                    time_remaining,
+                    intermission_remaining,
+                })
+            }
+            _ if self.is_final() => GameState::Final,
+            _ => GameState::Scheduled,
+        }
+    }
+
+    fn game_info(&self) -> GameInfo {
+        GameInfo {
+            game_id: self.id,
+            date: self.start_time_utc,
+            home: GameTeam::from(&self.home_team),
+            away: GameTeam::from(&self.away_team),
+            state: self.game_state(),
+            time_tbd: self.game_schedule_state == "TBD",
+            series: None,
+            special_event: self.special_event.as_ref().map(SpecialEvent::label),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClubSchedule {
+    pub games: Vec<WebGame>,
+}
+
+impl ClubSchedule {
+    fn todays_game(&self, utc_now: &DateTime<Utc>, tz: Tz) -> Option<&WebGame> {
+        let local_now = utc_now.with_timezone(&tz);
+        self.games
+            .iter()
+            .find(|game| game.start_time_utc.with_timezone(&tz).date() == local_now.date())
+    }
+
+    fn next_game(&self, utc_now: &DateTime<Utc>) -> Option<&WebGame> {
+        self.games
+            .iter()
+            .find(|game| game.start_time_utc > *utc_now && !game.is_final())
+    }
+}
+
+/// The id of today's game in a club schedule response, used to decide which
+/// gamecenter landing to fetch.
+pub fn todays_game_id(
+    schedule_response_string: &str,
+    utc_now: &DateTime<Utc>,
+    tz: Tz,
+) -> Result<Option<usize>, EngineError> {
+    let schedule: ClubSchedule = from_json("club schedule", schedule_response_string)?;
+    Ok(schedule.todays_game(utc_now, tz).map(|game| game.id))
+}
+
+/// The season of the game a club schedule response will show, if it is a
+/// playoff game, used to decide whether to fetch series records.
+pub fn playoff_season(
+    schedule_response_string: &str,
+    utc_now: &DateTime<Utc>,
+    tz: Tz,
+) -> Result<Option<usize>, EngineError> {
+    let schedule: ClubSchedule = from_json("club schedule", schedule_response_string)?;
+    let game = schedule
+        .todays_game(utc_now, tz)
+        .or_else(|| schedule.next_game(utc_now));
+    Ok(game
+        .and_then(|game| decode_game_id(game.id))
+        .and_then(|game_id| match game_id.game_type {
+            GameType::Playoff(_) => Some(game_id.season),
+            _ => None,
+        }))
+}
+
+/// Attach the game's playoff series from a carousel response, if it is a
+/// playoff game and the carousel has the series.
+fn with_series(
+    mut game_info: GameInfo,
+    series_response_string: Option<&str>,
+    team_id: usize,
+) -> Result<GameInfo, EngineError> {
+    let round = decode_game_id(game_info.game_id).and_then(|game_id| match game_id.game_type {
+        GameType::Playoff(pgn) => Some(pgn.round),
+        _ => None,
+    });
+    if let (Some(round), Some(series_response_string)) = (round, series_response_string) {
+        game_info.series = find_series(series_response_string, round, team_id)?;
+    }
+    Ok(game_info)
+}
+
+/// Optional responses that add detail to the display when present.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Extras<'a> {
+    /// The playoff carousel, for series state.
+    pub series: Option<&'a str>,
+    /// League standings, for both teams' records before the game.
+    pub standings: Option<&'a str>,
+}
+
+/// Both teams' records, ours first, for a game that hasn't started.
+fn pregame_records(
+    game_info: &GameInfo,
+    standings_response_string: Option<&str>,
+    team_id: usize,
+) -> Result<Option<String>, EngineError> {
+    let standings_response_string = match (&game_info.state, standings_response_string) {
+        (GameState::Scheduled | GameState::Pregame, Some(standings)) => standings,
+        _ => return Ok(None),
+    };
+    let (ours, theirs) = if game_info.home.id == team_id {
+        (&game_info.home, &game_info.away)
+    } else {
+        (&game_info.away, &game_info.home)
+    };
+    records_line(
+        standings_response_string,
+        &ours.abbreviation,
+        &theirs.abbreviation,
+    )
+}
+
+impl NextUp {
+    pub fn new_web(
+        nickname: &str,
+        schedule_response_string: &str,
+        landing_response_string: Option<&str>,
+        extras: Extras,
+        team_id: usize,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Self, EngineError> {
+        let schedule: ClubSchedule = from_json("club schedule", schedule_response_string)?;
+
+        let next = if let Some(game) = schedule.todays_game(utc_now, tz) {
+            // A landing that can't be read still leaves the schedule's view
+            // of the game, without the score or clock.
+            let (landing, landing_error) = match landing_response_string
+                .map(|landing| from_json::<WebGame>("landing", landing))
+                .transpose()
+            {
+                Ok(landing) => (landing.filter(|landing| landing.id == game.id), None),
+                Err(err) => (None, Some(err)),
+            };
+            let game_info = with_series(
+                landing.as_ref().unwrap_or(game).game_info(),
+                extras.series,
+                team_id,
+            )?;
+            let next = Self {
+                records: pregame_records(&game_info, extras.standings, team_id)?,
+                ..Self::from_todays_game(nickname, &game_info, team_id, utc_now, tz)
+            };
+            match landing_error {
+                Some(err) => next.degraded(&err.to_string()),
+                None => next,
+            }
+        } else if let Some(game) = schedule.next_game(utc_now) {
+            let game_info = with_series(game.game_info(), extras.series, team_id)?;
+            Self {
+                records: pregame_records(&game_info, extras.standings, team_id)?,
+                ..Self::from_next_game(nickname, &game_info, team_id, utc_now, tz)
+            }
+        } else {
+            Self::no_games(nickname, utc_now, tz)
+        };
+        Ok(next)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FeedStatus, DEFAULT_TIME_ZONE, TEAM_ABBREVIATIONS, TEAM_NICKNAMES};
+
+    const NJD_SCHEDULE_TEXT: &str = include_str!("../data/web_NJD_schedule.json");
+    const NJD_BEFORE_LANDING_TEXT: &str = include_str!("../data/web_NJD_before_landing.json");
+    const NJD_PREGAME_LANDING_TEXT: &str = include_str!("../data/web_NJD_pregame_landing.json");
+    const NJD_DURING_01_LANDING_TEXT: &str = include_str!("../data/web_NJD_during_01_landing.json");
+    const NJD_DURING_02_LANDING_TEXT: &str = include_str!("../data/web_NJD_during_02_landing.json");
+    const NJD_DURING_03_LANDING_TEXT: &str = include_str!("../data/web_NJD_during_03_landing.json");
+    const NJD_DURING_04_LANDING_TEXT: &str = include_str!("../data/web_NJD_during_04_landing.json");
+    const NJD_DURING_05_LANDING_TEXT: &str = include_str!("../data/web_NJD_during_05_landing.json");
+    const NJD_DURING_06_LANDING_TEXT: &str = include_str!("../data/web_NJD_during_06_landing.json");
+    const NJD_DURING_07_LANDING_TEXT: &str = include_str!("../data/web_NJD_during_07_landing.json");
+    const NJD_AFTER_LANDING_TEXT: &str = include_str!("../data/web_NJD_after_landing.json");
+    const SJS_SCHEDULE_TEXT: &str = include_str!("../data/web_SJS_schedule.json");
+    const SJS_INT_LANDING_TEXT: &str = include_str!("../data/web_SJS_int_landing.json");
+    const SJS_AFTER_LANDING_TEXT: &str = include_str!("../data/web_SJS_after_landing.json");
+    const SJS_DONE_SCHEDULE_TEXT: &str = include_str!("../data/web_SJS_done_schedule.json");
+    const VGK_SCHEDULE_TEXT: &str = include_str!("../data/web_VGK_schedule.json");
+    const VGK_INT_LANDING_TEXT: &str = include_str!("../data/web_VGK_int_landing.json");
+    const VGK_SO_FINAL_LANDING_TEXT: &str = include_str!("../data/web_VGK_so_final_landing.json");
+    const NYI_PLAYOFF_SCHEDULE_TEXT: &str = include_str!("../data/web_NYI_playoff_schedule.json");
+    const NJD_SPECIAL_SCHEDULE_TEXT: &str = include_str!("../data/web_NJD_special_schedule.json");
+    const STANDINGS_TEXT: &str = include_str!("../data/web_standings.json");
+    const PLAYOFF_CAROUSEL_R1_TEXT: &str = include_str!("../data/web_playoff_carousel_r1.json");
+
+    #[allow(clippy::too_many_arguments)]
+    fn test_engine_in_time_zone(
+        today: &str,
+        tz: Tz,
+        team_id: usize,
+        schedule_response_string: &str,
+        landing_response_string: Option<&str>,
+        top: &str,
+        middle: &str,
+        bottom: &str,
+    ) -> NextUp {
+        let today = DateTime::parse_from_rfc3339(today)
+            .unwrap()
+            .with_timezone(&Utc);
+        let nickname = TEAM_NICKNAMES.get(&team_id).unwrap_or(&"Unknown");
+        let next_up = NextUp::new_web(
+            nickname,
+            schedule_response_string,
+            landing_response_string,
+            Extras::default(),
+            team_id,
+            &today,
+            tz,
+        )
+        .expect("test_engine_in_time_zone: next up to succeed");
+        assert_eq!(next_up.top, top);
+        assert_eq!(next_up.middle, middle);
+        assert_eq!(next_up.bottom, bottom);
+        next_up
+    }
+
+    fn test_engine_with_team(
+        today: &str,
+        team_id: usize,
+        schedule_response_string: &str,
+        landing_response_string: Option<&str>,
+        top: &str,
+        middle: &str,
+        bottom: &str,
+    ) -> NextUp {
+        test_engine_in_time_zone(
+            today,
+            DEFAULT_TIME_ZONE,
+            team_id,
+            schedule_response_string,
+            landing_response_string,
+            top,
+            middle,
+            bottom,
+        )
+    }
+
+    fn test_njd_live(landing_response_string: &str, top: &str, bottom: &str) -> NextUp {
+        test_engine_with_team(
+            "2021-03-21T17:00:00Z",
+            1,
+            NJD_SCHEDULE_TEXT,
+            Some(landing_response_string),
+            top,
+            "@ Pittsburgh Penguins",
+            bottom,
+        )
+    }
+
+    #[test]
+    fn test_club_schedule() {
+        let schedule: ClubSchedule = serde_json::from_str(NJD_SCHEDULE_TEXT).expect("from_str");
+        assert_eq!(schedule.games.len(), 3);
+        let game = &schedule.games[1];
+        assert_eq!(game.id, 2020020151);
+        assert_eq!(game.away_team.abbrev, "NJD");
+        assert_eq!(game.home_team.full_name(), "Pittsburgh Penguins");
+    }
+
+    #[test]
+    fn test_team_ids_match_web_api() {
+        for text in [
+            NJD_SCHEDULE_TEXT,
+            SJS_SCHEDULE_TEXT,
+            SJS_DONE_SCHEDULE_TEXT,
+            VGK_SCHEDULE_TEXT,
+            NYI_PLAYOFF_SCHEDULE_TEXT,
+            NJD_SPECIAL_SCHEDULE_TEXT,
+        ] {
+            let schedule: ClubSchedule = serde_json::from_str(text).expect("from_str");
+            for team in schedule
+                .games
+                .iter()
+                .flat_map(|game| [&game.away_team, &game.home_team])
+            {
+                assert_eq!(
+                    TEAM_ABBREVIATIONS.get(&team.id),
+                    Some(&team.abbrev.as_str()),
+                    "team {}",
+                    team.id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_todays_game_id() {
+        let today = DateTime::parse_from_rfc3339("2021-03-21T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            todays_game_id(NJD_SCHEDULE_TEXT, &today, DEFAULT_TIME_ZONE).unwrap(),
+            Some(2020020151)
+        );
+        let today = DateTime::parse_from_rfc3339("2021-03-20T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            todays_game_id(NJD_SCHEDULE_TEXT, &today, DEFAULT_TIME_ZONE).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_period_ordinal() {
+        let ordinal = |number, period_type: &str| {
+            PeriodDescriptor {
+                number,
+                period_type: period_type.to_string(),
+            }
+            .ordinal()
+        };
+        assert_eq!(ordinal(1, "REG"), "1st");
+        assert_eq!(ordinal(3, "REG"), "3rd");
+        assert_eq!(ordinal(4, "OT"), "OT");
+        assert_eq!(ordinal(6, "OT"), "3OT");
+        assert_eq!(ordinal(5, "SO"), "SO");
+    }
+
+    #[test]
+    fn test_njd_before() {
+        test_engine_with_team(
+            "2021-03-19T10:00:00Z",
+            1,
+            NJD_SCHEDULE_TEXT,
+            None,
+            "Devils Next Up",
+            "@ Pittsburgh Penguins",
+            "Mar 21 @ 10:00AM",
+        );
+    }
+
+    #[test]
+    fn test_njd_before_landing() {
+        test_engine_with_team(
+            "2021-03-21T10:00:00Z",
+            1,
+            NJD_SCHEDULE_TEXT,
+            Some(NJD_BEFORE_LANDING_TEXT),
+            "Devils Next Up",
+            "@ Pittsburgh Penguins",
+            "Today @ 10:00AM",
+        );
+    }
+
+    #[test]
+    fn test_njd_before_two_days() {
+        test_engine_with_team(
+            "2021-03-20T17:00:00Z",
+            1,
+            NJD_SCHEDULE_TEXT,
+            None,
+            "Devils Next Up",
+            "@ Pittsburgh Penguins",
+            "Mar 21 @ 10:00AM",
+        );
+    }
+
+    #[test]
+    fn test_njd_pregame() {
+        test_engine_with_team(
+            "2021-03-21T17:00:00Z",
+            1,
+            NJD_SCHEDULE_TEXT,
+            Some(NJD_PREGAME_LANDING_TEXT),
+            "Pregame",
+            "@ Pittsburgh Penguins",
+            "Live",
+        );
+    }
+
+    #[test]
+    fn test_njd_during_01() {
+        let next_up = test_njd_live(NJD_DURING_01_LANDING_TEXT, "1st int|12:41", "NJD 0 - 1 PIT");
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(0));
+    }
+
+    #[test]
+    fn test_njd_during_02() {
+        let next_up = test_njd_live(NJD_DURING_02_LANDING_TEXT, "1st int|0:00", "NJD 0 - 1 PIT");
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(0));
+    }
+
+    #[test]
+    fn test_njd_during_03() {
+        let next_up = test_njd_live(NJD_DURING_03_LANDING_TEXT, "2nd | 18:32", "NJD 0 - 1 PIT");
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(0));
+    }
+
+    #[test]
+    fn test_njd_during_04() {
+        let next_up = test_njd_live(NJD_DURING_04_LANDING_TEXT, "2nd | 03:50", "NJD 1 - 1 PIT");
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(1));
+    }
+
+    #[test]
+    fn test_njd_during_05() {
+        let next_up = test_njd_live(NJD_DURING_05_LANDING_TEXT, "3rd | 08:20", "NJD 1 - 1 PIT");
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(1));
+    }
+
+    #[test]
+    fn test_njd_during_06() {
+        let next_up = test_njd_live(NJD_DURING_06_LANDING_TEXT, "3rd | 00:26", "NJD 1 - 1 PIT");
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(1));
+    }
+
+    #[test]
+    fn test_njd_during_07() {
+        let next_up = test_njd_live(NJD_DURING_07_LANDING_TEXT, "OT | 02:43", "NJD 1 - 1 PIT");
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(1));
+    }
+
+    #[test]
+    fn test_njd_after() {
+        let next_up = test_engine_with_team(
+            "2021-03-21T17:00:00Z",
+            1,
+            NJD_SCHEDULE_TEXT,
+            Some(NJD_AFTER_LANDING_TEXT),
+            "Final",
+            "@ Pittsburgh Penguins",
+            "NJD 2 - 1 PIT",
+        );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(2));
+    }
+
+    #[test]
+    fn test_njd_stale_landing_ignored() {
+        // A landing for some other game must not override today's game.
+        test_engine_with_team(
+            "2021-03-21T10:00:00Z",
+            1,
+            NJD_SCHEDULE_TEXT,
+            Some(SJS_INT_LANDING_TEXT),
+            "Devils Next Up",
+            "@ Pittsburgh Penguins",
+            "Today @ 10:00AM",
+        );
+    }
+
+    #[test]
+    fn test_njd_eastern_today() {
+        // 10PM Pacific the night before is already game day in New York.
+        test_engine_in_time_zone(
+            "2021-03-21T05:00:00Z",
+            chrono_tz::America::New_York,
+            1,
+            NJD_SCHEDULE_TEXT,
+            Some(NJD_BEFORE_LANDING_TEXT),
+            "Devils Next Up",
+            "@ Pittsburgh Penguins",
+            "Today @ 1:00PM",
+        );
+        test_engine_with_team(
+            "2021-03-21T05:00:00Z",
+            1,
+            NJD_SCHEDULE_TEXT,
+            None,
+            "Devils Next Up",
+            "@ Pittsburgh Penguins",
+            "Mar 21 @ 10:00AM",
+        );
+    }
+
+    #[test]
+    fn test_sjs_next() {
+        test_engine_with_team(
+            "2021-03-20T17:00:00Z",
+            28,
+            SJS_SCHEDULE_TEXT,
+            None,
+            "Sharks Next Up",
+            "vs St. Louis Blues",
+            "Today @ 6:00PM",
+        );
+    }
+
+    #[test]
+    fn test_sjs_int() {
+        let next_up = test_engine_with_team(
+            "2021-03-29T17:00:00Z",
+            28,
+            SJS_SCHEDULE_TEXT,
+            Some(SJS_INT_LANDING_TEXT),
+            "1st int|8:46",
+            "vs Minnesota Wild",
+            "SJS 1 - 2 MIN",
+        );
+        assert_eq!(next_up.home_score, Some(1));
+        assert_eq!(next_up.away_score, Some(2));
+    }
+
+    #[test]
+    fn test_vgk_home_int() {
+        let next_up = test_engine_with_team(
+            "2024-11-26T04:00:00Z",
+            54,
+            VGK_SCHEDULE_TEXT,
+            Some(VGK_INT_LANDING_TEXT),
+            "2nd int|10:12",
+            "vs Seattle Kraken",
+            "VGK 2 - 1 SEA",
+        );
+        assert_eq!(next_up.home_score, Some(2));
+        assert_eq!(next_up.away_score, Some(1));
+    }
+
+    #[test]
+    fn test_sea_away_int() {
+        test_engine_with_team(
+            "2024-11-26T04:00:00Z",
+            55,
+            VGK_SCHEDULE_TEXT,
+            Some(VGK_INT_LANDING_TEXT),
+            "2nd int|10:12",
+            "@ Vegas Golden Knights",
+            "SEA 1 - 2 VGK",
+        );
+    }
+
+    #[test]
+    fn test_vgk_shootout_final() {
+        let next_up = test_engine_with_team(
+            "2024-11-26T06:00:00Z",
+            54,
+            VGK_SCHEDULE_TEXT,
+            Some(VGK_SO_FINAL_LANDING_TEXT),
+            "Final",
+            "vs Seattle Kraken",
+            "VGK 3 - 2 SEA",
+        );
+        assert_eq!(next_up.home_score, Some(3));
+        assert_eq!(next_up.away_score, Some(2));
+    }
+
+    #[test]
+    fn test_vgk_time_tbd() {
+        test_engine_with_team(
+            "2024-11-27T17:00:00Z",
+            54,
+            VGK_SCHEDULE_TEXT,
+            None,
+            "Knights Next Up",
+            "@ Los Angeles Kings",
+            "Nov 29",
+        );
+    }
+
+    #[test]
+    fn test_sjs_after_landing() {
+        let next_up = test_engine_with_team(
+            "2021-04-02T17:00:00Z",
+            28,
+            SJS_SCHEDULE_TEXT,
+            Some(SJS_AFTER_LANDING_TEXT),
+            "Final",
+            "@ Los Angeles Kings",
+            "SJS 3 - 0 LAK",
+        );
+        assert_eq!(next_up.home_score, Some(0));
+        assert_eq!(next_up.away_score, Some(3));
+    }
+
+    #[test]
+    fn test_sjs_done() {
+        test_engine_with_team(
+            "2021-05-14T17:00:00Z",
+            28,
+            SJS_DONE_SCHEDULE_TEXT,
+            None,
+            "Sharks Next Up",
+            "No Games",
+            "",
+        );
+    }
+
+    #[test]
+    fn test_playoff_one() {
+        test_engine_with_team(
+            "2021-05-14T10:00:00Z",
+            2,
+            NYI_PLAYOFF_SCHEDULE_TEXT,
+            None,
+            "Islanders - Game 1",
+            "@ Pittsburgh Penguins",
+            "May 16 @ 9:00AM",
+        );
+    }
+
+    #[test]
+    fn test_playoff_series() {
+        let today = DateTime::parse_from_rfc3339("2021-05-14T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            playoff_season(NYI_PLAYOFF_SCHEDULE_TEXT, &today, DEFAULT_TIME_ZONE).unwrap(),
+            Some(2020)
+        );
+        assert_eq!(
+            playoff_season(NJD_SCHEDULE_TEXT, &today, DEFAULT_TIME_ZONE).unwrap(),
+            None
+        );
+
+        let next_up = NextUp::new_web(
+            "Islanders",
+            NYI_PLAYOFF_SCHEDULE_TEXT,
+            None,
+            Extras {
+                series: Some(PLAYOFF_CAROUSEL_R1_TEXT),
+                ..Extras::default()
+            },
+            2,
+            &today,
+            DEFAULT_TIME_ZONE,
+        )
+        .unwrap();
+        assert_eq!(next_up.top, "First Round - Game 1");
+        assert_eq!(next_up.middle, "@ Pittsburgh Penguins");
+        assert_eq!(next_up.series.as_deref(), Some("Series tied 0-0"));
+
+        // Regular season games ignore the carousel.
+        let next_up = NextUp::new_web(
+            "Devils",
+            NJD_SCHEDULE_TEXT,
+            Some(NJD_DURING_03_LANDING_TEXT),
+            Extras {
+                series: Some(PLAYOFF_CAROUSEL_R1_TEXT),
+                ..Extras::default()
+            },
+            1,
+            &DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            DEFAULT_TIME_ZONE,
+        )
+        .unwrap();
+        assert_eq!(next_up.series, None);
+    }
+
+    #[test]
+    fn test_preseason() {
+        test_engine_with_team(
+            "2023-09-20T17:00:00Z",
+            1,
+            NJD_SPECIAL_SCHEDULE_TEXT,
+            None,
+            "Devils Preseason",
+            "vs New York Rangers",
+            "Sep 25 @ 4:00PM",
+        );
+    }
+
+    #[test]
+    fn test_special_event() {
+        test_engine_with_team(
+            "2024-02-10T17:00:00Z",
+            1,
+            NJD_SPECIAL_SCHEDULE_TEXT,
+            None,
+            "Stadium Series",
+            "vs Philadelphia Flyers",
+            "Feb 17 @ 4:00PM",
+        );
+    }
+
+    #[test]
+    fn test_pregame_records() {
+        let records = |now: &str, landing: &str| {
+            let now = DateTime::parse_from_rfc3339(now)
+                .unwrap()
+                .with_timezone(&Utc);
+            NextUp::new_web(
+                "Devils",
+                NJD_SCHEDULE_TEXT,
+                Some(landing),
+                Extras {
+                    standings: Some(STANDINGS_TEXT),
+                    ..Extras::default()
+                },
+                1,
+                &now,
+                DEFAULT_TIME_ZONE,
+            )
+            .unwrap()
+            .records
+        };
+        let expected = Some("NJD 38-39-5 L3 | PIT 38-32-12 L2".to_string());
+        assert_eq!(
+            records("2021-03-21T10:00:00Z", NJD_BEFORE_LANDING_TEXT),
+            expected
+        );
+        assert_eq!(
+            records("2021-03-21T17:00:00Z", NJD_PREGAME_LANDING_TEXT),
+            expected
+        );
+        assert_eq!(
+            records("2021-03-21T17:00:00Z", NJD_DURING_03_LANDING_TEXT),
+            None
+        );
+    }
+
+    #[test]
+    fn test_truncated_responses() {
+        let today = DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let truncated_schedule = &NJD_SCHEDULE_TEXT[..NJD_SCHEDULE_TEXT.len() / 2];
+        assert!(matches!(
+            NextUp::new_web(
+                "Devils",
+                truncated_schedule,
+                None,
+                Extras::default(),
+                1,
+                &today,
+                DEFAULT_TIME_ZONE
+            ),
+            Err(EngineError::Json {
+                what: "club schedule",
+                ..
+            })
+        ));
+        assert!(todays_game_id(truncated_schedule, &today, DEFAULT_TIME_ZONE).is_err());
+
+        // A truncated landing falls back to the schedule, marked degraded.
+        let truncated_landing = &NJD_DURING_03_LANDING_TEXT[..NJD_DURING_03_LANDING_TEXT.len() / 2];
+        let next = NextUp::new_web(
+            "Devils",
+            NJD_SCHEDULE_TEXT,
+            Some(truncated_landing),
+            Extras::default(),
+            1,
+            &today,
+            DEFAULT_TIME_ZONE,
+        )
+        .expect("schedule alone");
+        assert_eq!(next.middle, "@ Pittsburgh Penguins");
+        assert_eq!(next.status, FeedStatus::Degraded);
+        assert!(next.error.as_deref().unwrap().contains("landing"));
+        assert_eq!(next.home_score, None);
+    }
+}
@@ -0,0 +1,230 @@
+//! Playoff series records from the NHL web API's playoff carousel, and how a
+//! series is described on the display.
+
+use crate::error::{from_json, EngineError};
+use serde::{Deserialize, Serialize};
+
+/// The carousel for the playoffs ending the season that starts in `season`.
+pub fn carousel_url(base: &str, season: usize) -> String {
+    format!("{}/playoff-series/carousel/{}{}/", base, season, season + 1)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Carousel {
+    rounds: Vec<CarouselRound>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CarouselRound {
+    round_number: usize,
+    series: Vec<SeriesRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SeriesTeam {
+    pub id: usize,
+    pub abbrev: String,
+    pub wins: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SeriesRecord {
+    pub series_letter: String,
+    pub round_number: usize,
+    pub top_seed: SeriesTeam,
+    pub bottom_seed: SeriesTeam,
+    pub needed_to_win: usize,
+}
+
+/// The series in `round` that `team_id` is playing, if the carousel has it.
+pub fn find_series(
+    carousel_response_string: &str,
+    round: usize,
+    team_id: usize,
+) -> Result<Option<SeriesRecord>, EngineError> {
+    let carousel: Carousel = from_json("playoff carousel", carousel_response_string)?;
+    Ok(carousel
+        .rounds
+        .into_iter()
+        .filter(|carousel_round| carousel_round.round_number == round)
+        .flat_map(|carousel_round| carousel_round.series)
+        .find(|series| series.top_seed.id == team_id || series.bottom_seed.id == team_id))
+}
+
+impl SeriesRecord {
+    /// Rounds one and two put the Eastern Conference series first; after
+    /// that there is one series per conference, then the final.
+    pub fn round_name(&self) -> String {
+        match (self.round_number, self.series_letter.as_str()) {
+            (1, _) => "First Round".to_string(),
+            (2, _) => "Second Round".to_string(),
+            (3, "M") => "East Final".to_string(),
+            (3, "N") => "West Final".to_string(),
+            (3, _) => "Conference Final".to_string(),
+            (4, _) => "Stanley Cup Final".to_string(),
+            _ => "Playoffs".to_string(),
+        }
+    }
+
+    fn played(&self) -> usize {
+        self.top_seed.wins + self.bottom_seed.wins
+    }
+
+    /// The team with more wins, or `None` if the series is tied.
+    fn leader(&self) -> Option<&SeriesTeam> {
+        if self.top_seed.wins > self.bottom_seed.wins {
+            Some(&self.top_seed)
+        } else if self.bottom_seed.wins > self.top_seed.wins {
+            Some(&self.bottom_seed)
+        } else {
+            None
+        }
+    }
+
+    fn leader_wins(&self) -> usize {
+        self.top_seed.wins.max(self.bottom_seed.wins)
+    }
+
+    fn trailer_wins(&self) -> usize {
+        self.top_seed.wins.min(self.bottom_seed.wins)
+    }
+
+    /// Whether game `game` of the series only happens if the games before it
+    /// don't settle the series.
+    pub fn is_if_necessary(&self, game: usize) -> bool {
+        let played = self.played();
+        if game <= played {
+            return false;
+        }
+        self.leader_wins() + (game - 1 - played) >= self.needed_to_win
+    }
+
+    /// "SJS leads 2-1", "Series tied 3-3" or "TBL wins 4-1".
+    pub fn status(&self) -> String {
+        match self.leader() {
+            Some(leader) if leader.wins >= self.needed_to_win => {
+                format!(
+                    "{} wins {}-{}",
+                    leader.abbrev,
+                    leader.wins,
+                    self.trailer_wins()
+                )
+            }
+            Some(leader) => format!(
+                "{} leads {}-{}",
+                leader.abbrev,
+                leader.wins,
+                self.trailer_wins()
+            ),
+            None => format!("Series tied {}-{}", self.leader_wins(), self.trailer_wins()),
+        }
+    }
+
+    /// Whether game `game` settles the series either way.
+    fn is_deciding(&self, game: usize) -> bool {
+        self.top_seed.wins + 1 == self.needed_to_win
+            && self.bottom_seed.wins + 1 == self.needed_to_win
+            && game == self.played() + 1
+    }
+
+    /// The series line for game `game`, flagged when it may not be played
+    /// or when it decides the series.
+    pub fn status_for_game(&self, game: usize) -> String {
+        if self.is_if_necessary(game) {
+            format!("If necessary - {}", self.status())
+        } else if self.is_deciding(game) {
+            format!("Game {} - {}", game, self.status())
+        } else {
+            self.status()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const R1_TEXT: &str = include_str!("../data/web_playoff_carousel_r1.json");
+    const R2_TEXT: &str = include_str!("../data/web_playoff_carousel_r2.json");
+    const R3_TEXT: &str = include_str!("../data/web_playoff_carousel_r3.json");
+    const R4_TEXT: &str = include_str!("../data/web_playoff_carousel_r4.json");
+
+    const NYI: usize = 2;
+    const SJS: usize = 28;
+
+    fn series(carousel: &str, round: usize, team_id: usize) -> SeriesRecord {
+        find_series(carousel, round, team_id)
+            .expect("carousel")
+            .expect("series")
+    }
+
+    #[test]
+    fn test_carousel_url() {
+        assert_eq!(
+            carousel_url(crate::nhl_web::NHL_WEB_API_BASE, 2020),
+            "https://api-web.nhle.com/v1/playoff-series/carousel/20202021/"
+        );
+    }
+
+    #[test]
+    fn test_first_round() {
+        let nyi = series(R1_TEXT, 1, NYI);
+        assert_eq!(nyi.series_letter, "A");
+        assert_eq!(nyi.round_name(), "First Round");
+        assert_eq!(nyi.status(), "Series tied 0-0");
+        assert!(!nyi.is_if_necessary(1));
+        assert!(!nyi.is_if_necessary(4));
+        assert!(nyi.is_if_necessary(5));
+        assert!(find_series(R1_TEXT, 2, NYI).unwrap().is_none());
+        assert!(find_series(R1_TEXT, 1, 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_second_round() {
+        let nyi = series(R2_TEXT, 2, NYI);
+        assert_eq!(nyi.round_name(), "Second Round");
+        assert_eq!(nyi.status(), "NYI leads 3-1");
+        assert_eq!(nyi.status_for_game(5), "NYI leads 3-1");
+        assert_eq!(nyi.status_for_game(6), "If necessary - NYI leads 3-1");
+        assert_eq!(series(R2_TEXT, 2, SJS).status(), "Series tied 1-1");
+        assert_eq!(series(R2_TEXT, 1, SJS).status(), "SJS wins 4-3");
+    }
+
+    #[test]
+    fn test_conference_finals() {
+        let sjs = series(R3_TEXT, 3, SJS);
+        assert_eq!(sjs.round_name(), "West Final");
+        assert_eq!(sjs.status(), "SJS leads 2-1");
+        let nyi = series(R3_TEXT, 3, NYI);
+        assert_eq!(nyi.round_name(), "East Final");
+        assert_eq!(nyi.status(), "TBL leads 3-2");
+        assert!(!nyi.is_if_necessary(6));
+        assert!(nyi.is_if_necessary(7));
+    }
+
+    #[test]
+    fn test_final() {
+        let sjs = series(R4_TEXT, 4, SJS);
+        assert_eq!(sjs.round_name(), "Stanley Cup Final");
+        assert_eq!(sjs.status(), "Series tied 3-3");
+        assert_eq!(sjs.status_for_game(7), "Game 7 - Series tied 3-3");
+        assert_eq!(series(R4_TEXT, 3, NYI).status(), "TBL wins 4-3");
+        // Once a series is over, games still on the schedule won't happen.
+        assert!(series(R4_TEXT, 3, SJS).is_if_necessary(6));
+    }
+
+    #[test]
+    fn test_malformed_carousel() {
+        assert!(matches!(
+            find_series(&R2_TEXT[..R2_TEXT.len() / 2], 2, NYI),
+            Err(EngineError::Json {
+                what: "playoff carousel",
+                ..
+            })
+        ));
+    }
+}
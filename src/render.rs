@@ -0,0 +1,326 @@
+//! Renders a `NextUp` into the 296x128, four level grayscale bitmap the
+//! MagTag's e-ink display shows, so the device only has to blit it.
+
+use crate::NextUp;
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10, FONT_9X18_BOLD},
+        MonoFont, MonoTextStyle,
+    },
+    pixelcolor::{Gray2, GrayColor},
+    prelude::*,
+    primitives::{Line, PrimitiveStyle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+use std::convert::Infallible;
+
+pub const WIDTH: u32 = 296;
+pub const HEIGHT: u32 = 128;
+
+const BLACK: Gray2 = Gray2::new(0);
+const DARK_GRAY: Gray2 = Gray2::new(1);
+const LIGHT_GRAY: Gray2 = Gray2::new(2);
+const WHITE: Gray2 = Gray2::new(3);
+
+/// Two bit grayscale pixels, one per byte, in row-major order.
+pub struct Bitmap {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Bitmap {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![WHITE.luma(); (width * height) as usize],
+        }
+    }
+
+    /// Encode as a 4 bits per pixel palettized BMP, which CircuitPython's
+    /// `displayio.OnDiskBitmap` can load directly.
+    pub fn to_bmp(&self) -> Vec<u8> {
+        const FILE_HEADER_SIZE: u32 = 14;
+        const INFO_HEADER_SIZE: u32 = 40;
+        const PALETTE: [u8; 4] = [0x00, 0x55, 0xAA, 0xFF];
+        let palette_size = PALETTE.len() as u32 * 4;
+        let row_size = (self.width * 4 + 31) / 32 * 4;
+        let image_size = row_size * self.height;
+        let pixel_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + palette_size;
+        let file_size = pixel_offset + image_size;
+
+        let mut bmp = Vec::with_capacity(file_size as usize);
+        bmp.extend_from_slice(b"BM");
+        bmp.extend_from_slice(&file_size.to_le_bytes());
+        bmp.extend_from_slice(&0u32.to_le_bytes());
+        bmp.extend_from_slice(&pixel_offset.to_le_bytes());
+
+        bmp.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+        bmp.extend_from_slice(&(self.width as i32).to_le_bytes());
+        bmp.extend_from_slice(&(self.height as i32).to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&4u16.to_le_bytes());
+        bmp.extend_from_slice(&0u32.to_le_bytes());
+        bmp.extend_from_slice(&image_size.to_le_bytes());
+        bmp.extend_from_slice(&2835i32.to_le_bytes());
+        bmp.extend_from_slice(&2835i32.to_le_bytes());
+        bmp.extend_from_slice(&(PALETTE.len() as u32).to_le_bytes());
+        bmp.extend_from_slice(&(PALETTE.len() as u32).to_le_bytes());
+
+        for level in PALETTE.iter() {
+            bmp.extend_from_slice(&[*level, *level, *level, 0]);
+        }
+
+        // BMP rows run bottom to top.
+        for y in (0..self.height).rev() {
+            let start = (y * self.width) as usize;
+            let row = &self.pixels[start..start + self.width as usize];
+            let mut row_bytes: Vec<u8> = row
+                .chunks(2)
+                .map(|pair| (pair[0] << 4) | pair.get(1).copied().unwrap_or(0))
+                .collect();
+            row_bytes.resize(row_size as usize, 0);
+            bmp.extend_from_slice(&row_bytes);
+        }
+
+        bmp
+    }
+}
+
+impl OriginDimensions for Bitmap {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl DrawTarget for Bitmap {
+    type Color = Gray2;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x >= 0
+                && point.y >= 0
+                && (point.x as u32) < self.width
+                && (point.y as u32) < self.height
+            {
+                self.pixels[(point.y as u32 * self.width + point.x as u32) as usize] = color.luma();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cut `text` down to what fits in `width` pixels of `font`.
+fn fit_text(text: &str, font: &MonoFont, width: u32) -> String {
+    let char_width = font.character_size.width + font.character_spacing;
+    let max_chars = (width / char_width) as usize;
+    let fitted: String = text.chars().take(max_chars).collect();
+    fitted.trim_end().to_string()
+}
+
+fn draw_centered(bitmap: &mut Bitmap, text: &str, font: &MonoFont, color: Gray2, y: i32) {
+    let character_style = MonoTextStyle::new(font, color);
+    let text_style = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Top)
+        .build();
+    let text = fit_text(text, font, WIDTH - 8);
+    Text::with_text_style(
+        &text,
+        Point::new(WIDTH as i32 / 2, y),
+        character_style,
+        text_style,
+    )
+    .draw(bitmap)
+    .expect("infallible");
+}
+
+pub fn render_next_up(next_up: &NextUp) -> Bitmap {
+    let mut bitmap = Bitmap::new(WIDTH, HEIGHT);
+
+    draw_centered(&mut bitmap, &next_up.top, &FONT_10X20, BLACK, 8);
+    Line::new(Point::new(16, 34), Point::new(WIDTH as i32 - 17, 34))
+        .into_styled(PrimitiveStyle::with_stroke(LIGHT_GRAY, 2))
+        .draw(&mut bitmap)
+        .expect("infallible");
+    draw_centered(&mut bitmap, &next_up.middle, &FONT_10X20, BLACK, 46);
+    draw_centered(&mut bitmap, &next_up.bottom, &FONT_9X18_BOLD, BLACK, 78);
+    // One small line fits under the date; a series outranks the records.
+    if let Some(detail) = next_up.series.as_ref().or(next_up.records.as_ref()) {
+        draw_centered(&mut bitmap, detail, &FONT_6X10, DARK_GRAY, 100);
+    }
+
+    let time_style = MonoTextStyle::new(&FONT_6X10, DARK_GRAY);
+    let text_style = TextStyleBuilder::new()
+        .alignment(Alignment::Right)
+        .baseline(Baseline::Bottom)
+        .build();
+    Text::with_text_style(
+        &next_up.time,
+        Point::new(WIDTH as i32 - 4, HEIGHT as i32 - 3),
+        time_style,
+        text_style,
+    )
+    .draw(&mut bitmap)
+    .expect("infallible");
+
+    if next_up.stale {
+        let stale_style = MonoTextStyle::new(&FONT_6X10, DARK_GRAY);
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Left)
+            .baseline(Baseline::Bottom)
+            .build();
+        Text::with_text_style(
+            "not updated",
+            Point::new(4, HEIGHT as i32 - 3),
+            stale_style,
+            text_style,
+        )
+        .draw(&mut bitmap)
+        .expect("infallible");
+    }
+
+    bitmap
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use std::{env, fs};
+
+    fn next_up(top: &str, middle: &str, bottom: &str) -> NextUp {
+        NextUp {
+            top: top.to_string(),
+            middle: middle.to_string(),
+            bottom: bottom.to_string(),
+            time: "10:41AM".to_string(),
+            sleep: 60,
+            date: DateTime::parse_from_rfc3339("2021-03-21T17:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            ..NextUp::default()
+        }
+    }
+
+    /// Compare against a checked in golden image. Run with `UPDATE_GOLDEN=1`
+    /// to rewrite the golden images after an intentional layout change.
+    fn assert_golden(name: &str, next_up: &NextUp) {
+        let bmp = render_next_up(next_up).to_bmp();
+        let path = format!("{}/data/golden/{}.bmp", env!("CARGO_MANIFEST_DIR"), name);
+        if env::var("UPDATE_GOLDEN").is_ok() {
+            fs::write(&path, &bmp).expect("write golden");
+        }
+        let golden = fs::read(&path).expect("read golden");
+        assert!(golden == bmp, "{} does not match {}", name, path);
+    }
+
+    #[test]
+    fn test_bmp_header() {
+        let bmp = Bitmap::new(WIDTH, HEIGHT).to_bmp();
+        assert_eq!(&bmp[0..2], b"BM");
+        assert_eq!(bmp.len(), 14 + 40 + 16 + 148 * 128);
+        assert_eq!(
+            u32::from_le_bytes([bmp[2], bmp[3], bmp[4], bmp[5]]),
+            bmp.len() as u32
+        );
+        assert_eq!(
+            i32::from_le_bytes([bmp[18], bmp[19], bmp[20], bmp[21]]),
+            296
+        );
+        assert_eq!(
+            i32::from_le_bytes([bmp[22], bmp[23], bmp[24], bmp[25]]),
+            128
+        );
+        assert_eq!(u16::from_le_bytes([bmp[28], bmp[29]]), 4);
+        // A blank bitmap is all white, palette index 3.
+        assert!(bmp[70..].iter().all(|byte| *byte == 0x33));
+    }
+
+    #[test]
+    fn test_fit_text() {
+        assert_eq!(
+            fit_text("vs Columbus Blue Jackets and more", &FONT_10X20, WIDTH - 8),
+            "vs Columbus Blue Jackets and"
+        );
+        assert_eq!(fit_text("Live", &FONT_10X20, WIDTH - 8), "Live");
+    }
+
+    #[test]
+    fn test_render_live() {
+        assert_golden(
+            "live",
+            &next_up("2nd | 18:32", "@ Pittsburgh Penguins", "Live"),
+        );
+    }
+
+    #[test]
+    fn test_render_next_up() {
+        assert_golden(
+            "next_up",
+            &next_up("Sharks Next Up", "vs Minnesota Wild", "Mar 29 @ 7:30PM"),
+        );
+    }
+
+    #[test]
+    fn test_render_long_text() {
+        assert_golden(
+            "long_text",
+            &next_up(
+                "Cuda Next Up",
+                "vs Henderson Silver Knights of Nevada",
+                "Oct 22 @ 6:00PM",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_render_unavailable() {
+        assert_golden(
+            "unavailable",
+            &next_up(
+                "Sharks Next Up",
+                "Schedule unavailable",
+                "Retrying in 2 min",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_render_stale() {
+        assert_golden(
+            "stale",
+            &NextUp {
+                stale: true,
+                ..next_up("Sharks Next Up", "vs Minnesota Wild", "Today @ 7:30PM")
+            },
+        );
+    }
+
+    #[test]
+    fn test_render_series() {
+        assert_golden(
+            "series",
+            &NextUp {
+                series: Some("SJS leads 2-1".to_string()),
+                ..next_up("West Final - Game 4", "@ Minnesota Wild", "May 29 @ 6:00PM")
+            },
+        );
+    }
+
+    #[test]
+    fn test_render_records() {
+        assert_golden(
+            "records",
+            &NextUp {
+                records: Some("SJS 19-54-9 L3 | MIN 39-34-9 L4".to_string()),
+                ..next_up("Sharks Next Up", "vs Minnesota Wild", "Mar 29 @ 7:30PM")
+            },
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! Recording upstream traffic to a directory and replaying it later with a
+//! simulated clock, so what a device showed during a game can be reproduced.
+//!
+//! Each response is one JSON file named by its sequence number. On replay,
+//! every device request to the gateway moves the clock to the next recorded
+//! moment and each URL answers with its latest response as of that moment.
+//! Background pollers read the clock without moving it, so they can't eat
+//! into the recorded sequence.
+
+use crate::clock::Clock;
+use anyhow::{bail, Context, Error, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Responses recorded this close together were fetched for the same device
+/// request, so replay steps over them at once.
+const STEP_WINDOW_SECONDS: i64 = 10;
+
+/// The replay's simulated clock, as of the latest device request.
+pub struct ReplayClock(pub Arc<Replay>);
+
+impl Clock for ReplayClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Recording {
+    pub fetched_at: DateTime<Utc>,
+    pub url: String,
+    pub body: String,
+}
+
+fn recording_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) == Some("json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    next_index: AtomicUsize,
+}
+
+impl Recorder {
+    /// Record into `dir`, after any recordings already there.
+    pub fn new(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        let next_index = recording_paths(dir)?.len() + 1;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            next_index: AtomicUsize::new(next_index),
+        })
+    }
+
+    pub fn record(&self, url: &str, body: &str, fetched_at: DateTime<Utc>) -> Result<(), Error> {
+        let index = self.next_index.fetch_add(1, Ordering::SeqCst);
+        let path = self.dir.join(format!("{:06}.json", index));
+        let recording = Recording {
+            fetched_at,
+            url: url.to_string(),
+            body: body.to_string(),
+        };
+        fs::write(&path, serde_json::to_string_pretty(&recording)?)
+            .with_context(|| format!("writing {}", path.display()))?;
+        info!("recorded {} as {}", url, path.display());
+        Ok(())
+    }
+}
+
+pub struct Replay {
+    recordings: Vec<Recording>,
+    clock: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl Replay {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let recordings = recording_paths(dir)?
+            .iter()
+            .map(|path| {
+                let text = fs::read_to_string(path)?;
+                serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+            })
+            .collect::<Result<Vec<Recording>>>()?;
+        Self::from_recordings(recordings).with_context(|| format!("replaying {}", dir.display()))
+    }
+
+    pub fn from_recordings(mut recordings: Vec<Recording>) -> Result<Self> {
+        if recordings.is_empty() {
+            bail!("no recordings");
+        }
+        recordings.sort_by_key(|recording| recording.fetched_at);
+        Ok(Self {
+            recordings,
+            clock: Mutex::new(None),
+        })
+    }
+
+    /// Move the simulated clock on to the next recorded moment and return
+    /// it. Once every recording has been played the clock stays put.
+    pub fn advance(&self) -> DateTime<Utc> {
+        let mut clock = self.clock.lock().expect("replay clock");
+        let next = self.recordings.iter().find(|recording| match *clock {
+            Some(now) => recording.fetched_at > now,
+            None => true,
+        });
+        if let Some(next) = next {
+            let step_end = next.fetched_at + Duration::seconds(STEP_WINDOW_SECONDS);
+            let now = self
+                .recordings
+                .iter()
+                .map(|recording| recording.fetched_at)
+                .filter(|fetched_at| *fetched_at <= step_end)
+                .max()
+                .unwrap_or(next.fetched_at);
+            info!("replay clock now {}", now);
+            *clock = Some(now);
+        } else {
+            info!("replay finished");
+        }
+        clock.expect("replay clock set")
+    }
+
+    /// The simulated clock, or the first recorded moment before any device
+    /// request has moved it.
+    pub fn now(&self) -> DateTime<Utc> {
+        let clock = self.clock.lock().expect("replay clock");
+        clock.unwrap_or(self.recordings[0].fetched_at)
+    }
+
+    /// The latest response for `url` recorded at or before the simulated
+    /// clock.
+    pub fn response(&self, url: &str) -> Result<String, Error> {
+        let now = self.now();
+        self.recordings
+            .iter()
+            .rev()
+            .find(|recording| recording.url == url && recording.fetched_at <= now)
+            .map(|recording| recording.body.clone())
+            .with_context(|| format!("no recording of {} by {}", url, now))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{nhl_web, NextUp, DEFAULT_TIME_ZONE};
+    use std::env;
+
+    const NJD_SCHEDULE_TEXT: &str = include_str!("../data/web_NJD_schedule.json");
+    const NJD_LANDINGS: &[&str] = &[
+        include_str!("../data/web_NJD_during_01_landing.json"),
+        include_str!("../data/web_NJD_during_03_landing.json"),
+        include_str!("../data/web_NJD_during_05_landing.json"),
+        include_str!("../data/web_NJD_after_landing.json"),
+    ];
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn recording(url: &str, body: &str, fetched_at: &str) -> Recording {
+        Recording {
+            fetched_at: at(fetched_at),
+            url: url.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_record_and_load() {
+        let dir = env::temp_dir().join(format!("magtag_replay_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let recorder = Recorder::new(&dir).expect("recorder");
+        recorder
+            .record("https://a", "first", at("2021-03-21T17:00:00Z"))
+            .unwrap();
+        recorder
+            .record("https://b", "second", at("2021-03-21T17:00:01Z"))
+            .unwrap();
+        // A second session appends rather than overwriting.
+        Recorder::new(&dir)
+            .unwrap()
+            .record("https://a", "third", at("2021-03-21T17:05:00Z"))
+            .unwrap();
+        assert_eq!(recording_paths(&dir).unwrap().len(), 3);
+
+        let replay = Replay::load(&dir).expect("replay");
+        assert_eq!(replay.now(), at("2021-03-21T17:00:00Z"));
+        assert_eq!(replay.advance(), at("2021-03-21T17:00:01Z"));
+        // Reading the clock doesn't move it; only device requests do.
+        assert_eq!(replay.now(), at("2021-03-21T17:00:01Z"));
+        assert_eq!(replay.now(), at("2021-03-21T17:00:01Z"));
+        assert_eq!(replay.response("https://a").unwrap(), "first");
+        assert_eq!(replay.response("https://b").unwrap(), "second");
+        assert_eq!(replay.advance(), at("2021-03-21T17:05:00Z"));
+        assert_eq!(replay.response("https://a").unwrap(), "third");
+        assert_eq!(replay.response("https://b").unwrap(), "second");
+        assert!(replay.response("https://c").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(Replay::load(&dir).is_err());
+    }
+
+    #[test]
+    fn test_replay_clock_stops_at_end() {
+        let replay =
+            Replay::from_recordings(vec![recording("https://a", "a", "2021-03-21T17:00:00Z")])
+                .unwrap();
+        assert_eq!(replay.advance(), at("2021-03-21T17:00:00Z"));
+        assert_eq!(replay.advance(), at("2021-03-21T17:00:00Z"));
+        assert!(Replay::from_recordings(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_replay_game() {
+        // The schedule was fetched once and cached; the landing every poll.
+        let schedule_url = nhl_web::club_schedule_url(nhl_web::NHL_WEB_API_BASE, "NJD");
+        let landing_url = nhl_web::landing_url(nhl_web::NHL_WEB_API_BASE, 2020020151);
+        let mut recordings = vec![recording(
+            &schedule_url,
+            NJD_SCHEDULE_TEXT,
+            "2021-03-21T17:20:00Z",
+        )];
+        for (minutes, landing) in NJD_LANDINGS.iter().enumerate() {
+            let fetched_at = at("2021-03-21T17:20:05Z") + Duration::minutes(minutes as i64 * 30);
+            recordings.push(Recording {
+                fetched_at,
+                url: landing_url.clone(),
+                body: landing.to_string(),
+            });
+        }
+        let replay = Replay::from_recordings(recordings).unwrap();
+
+        let tops: Vec<String> = (0..NJD_LANDINGS.len())
+            .map(|_| {
+                let now = replay.advance();
+                let schedule = replay.response(&schedule_url).unwrap();
+                let landing = replay.response(&landing_url).unwrap();
+                NextUp::new_web(
+                    "Devils",
+                    &schedule,
+                    Some(&landing),
+                    nhl_web::Extras::default(),
+                    1,
+                    &now,
+                    DEFAULT_TIME_ZONE,
+                )
+                .unwrap()
+                .top
+            })
+            .collect();
+        assert_eq!(
+            tops,
+            ["1st int|12:41", "2nd | 18:32", "3rd | 08:20", "Final"]
+        );
+    }
+}
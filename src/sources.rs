@@ -0,0 +1,545 @@
+//! Schedule sources behind a common trait, so any combination of them can be
+//! composed into a single `NextUp`.
+
+use crate::{
+    ahl, barracuda_games, get_nhl_next_up, get_standings_next_up, load_text, state::AppState,
+    NextUp, NextUpState, TEAM_NICKNAMES,
+};
+use anyhow::{bail, Context, Error, Result};
+use async_std::task;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use log::warn;
+use std::{cmp::Reverse, sync::Arc};
+
+/// Header shown when no source could be reached.
+const COMBINED_NEXT_UP: &str = "Next Up";
+
+/// The sources `/combined` uses when none are given.
+pub const DEFAULT_SOURCES: &str = "nhl:28,ahl:sjb,events";
+
+#[async_trait]
+pub trait ScheduleSource: Send + Sync {
+    /// The spec the source was parsed from, e.g. `nhl:28`.
+    fn name(&self) -> String;
+
+    /// What this source would show on its own right now.
+    async fn next_up(
+        &self,
+        state: &AppState,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<NextUp, Error>;
+}
+
+pub struct NhlSource {
+    pub team_id: usize,
+}
+
+#[async_trait]
+impl ScheduleSource for NhlSource {
+    fn name(&self) -> String {
+        format!("nhl:{}", self.team_id)
+    }
+
+    async fn next_up(
+        &self,
+        state: &AppState,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<NextUp, Error> {
+        get_nhl_next_up(state, self.team_id, utc_now, tz).await
+    }
+}
+
+/// The team's standings, which `/combined` shows on days without a game.
+pub struct StandingsSource {
+    pub team_id: usize,
+}
+
+#[async_trait]
+impl ScheduleSource for StandingsSource {
+    fn name(&self) -> String {
+        format!("standings:{}", self.team_id)
+    }
+
+    async fn next_up(
+        &self,
+        state: &AppState,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<NextUp, Error> {
+        get_standings_next_up(state, self.team_id, utc_now, tz).await
+    }
+}
+
+pub struct BarracudaSource;
+
+#[async_trait]
+impl ScheduleSource for BarracudaSource {
+    fn name(&self) -> String {
+        format!("ahl:{}", ahl::BARRACUDA_TEAM.to_lowercase())
+    }
+
+    async fn next_up(
+        &self,
+        state: &AppState,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<NextUp, Error> {
+        let (games, problem) = barracuda_games(state).await;
+        let next = NextUp::new_barracuda_event(utc_now, tz, games);
+        Ok(match problem {
+            Some(problem) => next.degraded(&problem),
+            None => next,
+        })
+    }
+}
+
+pub struct EventsSource;
+
+#[async_trait]
+impl ScheduleSource for EventsSource {
+    fn name(&self) -> String {
+        "events".to_string()
+    }
+
+    async fn next_up(
+        &self,
+        state: &AppState,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<NextUp, Error> {
+        let events_text = load_text(state, "events", &state.config.events).await?;
+        Ok(NextUp::new_event(utc_now, tz, &events_text)?)
+    }
+}
+
+fn parse_team_id(team: &str, spec: &str) -> Result<usize> {
+    let team_id = team
+        .parse::<usize>()
+        .with_context(|| format!("NHL team id in {}", spec))?;
+    if !TEAM_NICKNAMES.contains_key(&team_id) {
+        bail!("unknown NHL team {}", team_id);
+    }
+    Ok(team_id)
+}
+
+/// Parse one source spec: `nhl:<team id>`, `standings:<team id>`, `ahl:sjb`
+/// or `events`.
+pub fn parse_source(spec: &str) -> Result<Arc<dyn ScheduleSource>> {
+    let spec = spec.trim();
+    let (kind, team) = match spec.split_once(':') {
+        Some((kind, team)) => (kind, Some(team)),
+        None => (spec, None),
+    };
+    let source: Arc<dyn ScheduleSource> = match (kind.to_ascii_lowercase().as_str(), team) {
+        ("nhl", Some(team)) => Arc::new(NhlSource {
+            team_id: parse_team_id(team, spec)?,
+        }),
+        ("standings", Some(team)) => Arc::new(StandingsSource {
+            team_id: parse_team_id(team, spec)?,
+        }),
+        ("ahl", Some(team)) if team.eq_ignore_ascii_case(ahl::BARRACUDA_TEAM) => {
+            Arc::new(BarracudaSource)
+        }
+        ("ahl", Some(team)) => bail!("unsupported AHL team {}", team),
+        ("events", None) => Arc::new(EventsSource),
+        _ => bail!("unknown source {}", spec),
+    };
+    Ok(source)
+}
+
+/// NHL sources for a comma separated list of team ids, e.g. `28,1,22`.
+pub fn parse_teams(teams: &str) -> Result<Vec<Arc<dyn ScheduleSource>>> {
+    let sources = teams
+        .split(',')
+        .filter(|team| !team.trim().is_empty())
+        .map(|team| {
+            let team_id = parse_team_id(team.trim(), teams)?;
+            Ok(Arc::new(NhlSource { team_id }) as Arc<dyn ScheduleSource>)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if sources.is_empty() {
+        bail!("no teams given");
+    }
+    Ok(sources)
+}
+
+/// Parse a comma separated list of source specs.
+pub fn parse_sources(specs: &str) -> Result<Vec<Arc<dyn ScheduleSource>>> {
+    let sources = specs
+        .split(',')
+        .filter(|spec| !spec.trim().is_empty())
+        .map(parse_source)
+        .collect::<Result<Vec<_>>>()?;
+    if sources.is_empty() {
+        bail!("no sources given");
+    }
+    Ok(sources)
+}
+
+/// Choose what to show from each source's `NextUp`: the first live game,
+/// otherwise a standings page if nothing is on today, otherwise the soonest
+/// upcoming item, otherwise the most recently finished game. Ties go to the
+/// earlier source. With none of those, the first source's display is used.
+pub fn pick(candidates: Vec<NextUp>, utc_now: &DateTime<Utc>, tz: Tz) -> Option<NextUp> {
+    let today = utc_now.with_timezone(&tz).date();
+    let game_today = candidates.iter().any(|next| match next.state {
+        NextUpState::Live => true,
+        NextUpState::Upcoming | NextUpState::Final => next.date.with_timezone(&tz).date() == today,
+        NextUpState::NoGames | NextUpState::Standings => false,
+    });
+    let with_state = |state| candidates.iter().position(|next| next.state == state);
+    let chosen = with_state(NextUpState::Live)
+        .or_else(|| {
+            if game_today {
+                None
+            } else {
+                with_state(NextUpState::Standings)
+            }
+        })
+        .or_else(|| {
+            candidates
+                .iter()
+                .enumerate()
+                .filter(|(_, next)| next.state == NextUpState::Upcoming)
+                .min_by_key(|(_, next)| next.date)
+                .map(|(index, _)| index)
+        })
+        .or_else(|| {
+            candidates
+                .iter()
+                .enumerate()
+                .filter(|(_, next)| next.state == NextUpState::Final)
+                .min_by_key(|(_, next)| Reverse(next.date))
+                .map(|(index, _)| index)
+        })
+        .unwrap_or(0);
+    candidates.into_iter().nth(chosen)
+}
+
+/// Ask every source for its `NextUp`, all at once, and pick one. A source
+/// that fails is left out and the result marked degraded; if every source
+/// fails the result says the schedule is unavailable.
+pub async fn combined_next_up(
+    state: &AppState,
+    sources: &[Arc<dyn ScheduleSource>],
+    utc_now: &DateTime<Utc>,
+    tz: Tz,
+) -> NextUp {
+    let fetches: Vec<_> = sources
+        .iter()
+        .map(|source| {
+            let (state, source, utc_now) = (state.clone(), source.clone(), *utc_now);
+            task::spawn(async move { source.next_up(&state, &utc_now, tz).await })
+        })
+        .collect();
+    let mut candidates = vec![];
+    let mut errors = vec![];
+    // Awaited in list order, so ties still go to the earlier source.
+    for (source, fetch) in sources.iter().zip(fetches) {
+        match fetch.await {
+            Ok(next) => candidates.push(next),
+            Err(err) => {
+                warn!("source {} unavailable: {:#}", source.name(), err);
+                errors.push(format!("{}: {:#}", source.name(), err));
+            }
+        }
+    }
+    let error = errors.join("; ");
+    match pick(candidates, utc_now, tz) {
+        Some(next) if errors.is_empty() => next,
+        Some(next) => next.degraded(&error),
+        None => NextUp::unavailable(COMBINED_NEXT_UP, &error, &state.config.sleep, utc_now, tz),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ahl::parse_schedule, nhl_web::Extras, FeedStatus, DEFAULT_TIME_ZONE, RETRY_SLEEP};
+    use anyhow::anyhow;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    const SCHEDULE_TEXT: &str = include_str!("../data/web_NJD_schedule.json");
+    const DURING_TEXT: &str = include_str!("../data/web_NJD_during_03_landing.json");
+    const BARRACUDA_TEXT: &str = include_str!("../data/barracuda.html");
+    const EVENTS_TEXT: &str = include_str!("../data/events.toml");
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn candidate(middle: &str, state: NextUpState, date: &str) -> NextUp {
+        NextUp {
+            middle: middle.to_string(),
+            state,
+            date: at(date),
+            ..NextUp::default()
+        }
+    }
+
+    /// A source that always returns the same item, or fails when it has none.
+    /// With `meet`, it counts itself in and only returns once that many
+    /// sources sharing the counter have started.
+    struct FakeSource {
+        name: &'static str,
+        item: Option<(NextUpState, &'static str)>,
+        meet: Option<(Arc<AtomicUsize>, usize)>,
+    }
+
+    #[async_trait]
+    impl ScheduleSource for FakeSource {
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        async fn next_up(
+            &self,
+            _state: &AppState,
+            _utc_now: &DateTime<Utc>,
+            _tz: Tz,
+        ) -> Result<NextUp, Error> {
+            if let Some((started, count)) = &self.meet {
+                started.fetch_add(1, Ordering::SeqCst);
+                while started.load(Ordering::SeqCst) < *count {
+                    task::yield_now().await;
+                }
+            }
+            match self.item {
+                Some((state, date)) => Ok(candidate(self.name, state, date)),
+                None => Err(anyhow!("offline")),
+            }
+        }
+    }
+
+    fn fake(
+        name: &'static str,
+        item: Option<(NextUpState, &'static str)>,
+    ) -> Arc<dyn ScheduleSource> {
+        Arc::new(FakeSource {
+            name,
+            item,
+            meet: None,
+        })
+    }
+
+    #[test]
+    fn test_combined_next_up() {
+        let now = at("2021-03-21T12:00:00Z");
+        let state = AppState::test();
+        let combine = |sources: Vec<Arc<dyn ScheduleSource>>| {
+            async_std::task::block_on(combined_next_up(&state, &sources, &now, DEFAULT_TIME_ZONE))
+                .with_sleep(&state.config.sleep, &now)
+        };
+
+        let next = combine(vec![
+            fake("a", Some((NextUpState::Upcoming, "2021-03-22T17:00:00Z"))),
+            fake("b", Some((NextUpState::Upcoming, "2021-03-21T17:00:00Z"))),
+        ]);
+        assert_eq!(next.middle, "b");
+        assert_eq!(next.status, FeedStatus::Ok);
+        assert_eq!(next.error, None);
+        assert_eq!(next.sleep, state.config.sleep.max_secs);
+
+        let next = combine(vec![
+            fake("a", None),
+            fake("b", Some((NextUpState::Upcoming, "2021-03-21T17:00:00Z"))),
+        ]);
+        assert_eq!(next.middle, "b");
+        assert_eq!(next.status, FeedStatus::Degraded);
+        assert_eq!(next.error.as_deref(), Some("a: offline"));
+        assert_eq!(next.sleep, RETRY_SLEEP);
+
+        let next = combine(vec![fake("a", None), fake("b", None)]);
+        assert_eq!(next.status, FeedStatus::Error);
+        assert_eq!(next.top, COMBINED_NEXT_UP);
+        assert_eq!(next.middle, "Schedule unavailable");
+        assert_eq!(next.bottom, "Retrying in 2 min");
+        assert_eq!(next.error.as_deref(), Some("a: offline; b: offline"));
+        assert_eq!(next.sleep, RETRY_SLEEP);
+        assert!(!next.stale);
+    }
+
+    #[test]
+    fn test_combined_fetches_concurrently() {
+        // Neither source returns until both have started, which fetching
+        // one after the other never gets to.
+        let started = Arc::new(AtomicUsize::new(0));
+        let meeting = |name| -> Arc<dyn ScheduleSource> {
+            Arc::new(FakeSource {
+                name,
+                item: Some((NextUpState::Upcoming, "2021-03-21T17:00:00Z")),
+                meet: Some((started.clone(), 2)),
+            })
+        };
+        let sources = vec![meeting("first"), meeting("second")];
+        let state = AppState::test();
+        let now = at("2021-03-21T12:00:00Z");
+        let combined = combined_next_up(&state, &sources, &now, DEFAULT_TIME_ZONE);
+        let next = async_std::task::block_on(async_std::future::timeout(
+            Duration::from_secs(10),
+            combined,
+        ))
+        .expect("sources fetched one at a time");
+        assert_eq!(next.middle, "first");
+    }
+
+    #[test]
+    fn test_parse_teams() {
+        let names = |teams| -> Vec<String> {
+            parse_teams(teams)
+                .unwrap()
+                .iter()
+                .map(|source| source.name())
+                .collect()
+        };
+        assert_eq!(names("28,1,22"), ["nhl:28", "nhl:1", "nhl:22"]);
+        assert_eq!(names(" 1, 28,"), ["nhl:1", "nhl:28"]);
+        for bad in &["", ",", "sharks", "28,99"] {
+            assert!(parse_teams(bad).is_err(), "{} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        let sources = parse_sources(DEFAULT_SOURCES).expect("sources");
+        let names: Vec<String> = sources.iter().map(|source| source.name()).collect();
+        assert_eq!(names, ["nhl:28", "ahl:sjb", "events"]);
+        assert_eq!(parse_sources("nhl:1, AHL:SJB").unwrap().len(), 2);
+        assert_eq!(
+            parse_sources("standings:28").unwrap()[0].name(),
+            "standings:28"
+        );
+
+        for bad in &[
+            "",
+            "nhl",
+            "nhl:sharks",
+            "nhl:99",
+            "ahl:iowa",
+            "mlb:1",
+            "events:1",
+            "standings",
+            "standings:99",
+        ] {
+            assert!(parse_sources(bad).is_err(), "{} should not parse", bad);
+        }
+    }
+
+    /// Pick as of a day before any of the candidates.
+    fn pick_early(candidates: Vec<NextUp>) -> Option<NextUp> {
+        pick(candidates, &at("2021-03-19T12:00:00Z"), DEFAULT_TIME_ZONE)
+    }
+
+    #[test]
+    fn test_pick_live_wins() {
+        let next = pick_early(vec![
+            candidate("upcoming", NextUpState::Upcoming, "2021-03-21T17:00:00Z"),
+            candidate("live", NextUpState::Live, "2021-03-21T19:00:00Z"),
+            candidate("also live", NextUpState::Live, "2021-03-21T16:00:00Z"),
+        ])
+        .unwrap();
+        assert_eq!(next.middle, "live");
+    }
+
+    #[test]
+    fn test_pick_soonest_wins() {
+        let next = pick_early(vec![
+            candidate("nothing", NextUpState::NoGames, "2021-03-20T17:00:00Z"),
+            candidate("final", NextUpState::Final, "2021-03-20T18:00:00Z"),
+            candidate("later", NextUpState::Upcoming, "2021-03-22T17:00:00Z"),
+            candidate("sooner", NextUpState::Upcoming, "2021-03-21T17:00:00Z"),
+            candidate("tied", NextUpState::Upcoming, "2021-03-21T17:00:00Z"),
+        ])
+        .unwrap();
+        assert_eq!(next.middle, "sooner");
+    }
+
+    #[test]
+    fn test_pick_fallbacks() {
+        let next = pick_early(vec![
+            candidate("nothing", NextUpState::NoGames, "2021-03-20T17:00:00Z"),
+            candidate("earlier", NextUpState::Final, "2021-03-20T17:00:00Z"),
+            candidate("latest", NextUpState::Final, "2021-03-20T18:00:00Z"),
+        ])
+        .unwrap();
+        assert_eq!(next.middle, "latest");
+
+        let next = pick_early(vec![
+            candidate("first", NextUpState::NoGames, "2021-03-20T17:00:00Z"),
+            candidate("second", NextUpState::NoGames, "2021-03-20T17:00:00Z"),
+        ])
+        .unwrap();
+        assert_eq!(next.middle, "first");
+
+        assert!(pick_early(vec![]).is_none());
+    }
+
+    #[test]
+    fn test_pick_standings_on_off_days() {
+        let candidates = || {
+            vec![
+                candidate("final", NextUpState::Final, "2021-03-20T04:00:00Z"),
+                candidate("upcoming", NextUpState::Upcoming, "2021-03-23T02:00:00Z"),
+                candidate("standings", NextUpState::Standings, "2021-03-21T19:00:00Z"),
+            ]
+        };
+        let pick_at = |candidates, now| pick(candidates, &at(now), DEFAULT_TIME_ZONE).unwrap();
+
+        // Nothing on the 21st in Pacific time.
+        assert_eq!(
+            pick_at(candidates(), "2021-03-21T19:00:00Z").middle,
+            "standings"
+        );
+        // Game day, both before the game and after it ends.
+        assert_eq!(
+            pick_at(candidates(), "2021-03-22T19:00:00Z").middle,
+            "upcoming"
+        );
+        assert_ne!(
+            pick_at(candidates(), "2021-03-20T05:00:00Z").middle,
+            "standings"
+        );
+        let mut with_live = candidates();
+        with_live.push(candidate("live", NextUpState::Live, "2021-03-21T18:00:00Z"));
+        assert_eq!(pick_at(with_live, "2021-03-21T19:00:00Z").middle, "live");
+    }
+
+    #[test]
+    fn test_pick_across_sources() {
+        let tz = DEFAULT_TIME_ZONE;
+        let during = at("2021-03-21T17:45:00Z");
+        let nhl = NextUp::new_web(
+            "Devils",
+            SCHEDULE_TEXT,
+            Some(DURING_TEXT),
+            Extras::default(),
+            1,
+            &during,
+            tz,
+        )
+        .expect("nhl");
+        let barracuda =
+            NextUp::new_barracuda_event(&during, tz, parse_schedule(BARRACUDA_TEXT).unwrap());
+        let events = NextUp::new_event(&during, tz, EVENTS_TEXT).expect("events");
+        assert_eq!(nhl.state, NextUpState::Live);
+
+        let next = pick(vec![barracuda, events, nhl], &during, tz).unwrap();
+        assert_eq!(next.middle, "@ Pittsburgh Penguins");
+
+        let before_cuda = at("2022-10-20T17:00:00Z");
+        let barracuda =
+            NextUp::new_barracuda_event(&before_cuda, tz, parse_schedule(BARRACUDA_TEXT).unwrap());
+        let events = NextUp::new_event(&before_cuda, tz, EVENTS_TEXT).expect("events");
+        let next = pick(vec![events, barracuda], &before_cuda, tz).unwrap();
+        assert_eq!(next.middle, "vs Henderson Silver Knights");
+    }
+}
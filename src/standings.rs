@@ -0,0 +1,222 @@
+//! League standings from the NHL web API, shown on days without a game.
+
+use crate::{
+    error::{from_json, EngineError},
+    nhl_web::LocalizedString,
+    NextUp, NextUpState, SourceKind,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+pub fn standings_url(base: &str) -> String {
+    format!("{}/standings/now", base)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamStanding {
+    pub team_abbrev: LocalizedString,
+    pub division_name: String,
+    pub division_sequence: usize,
+    pub games_played: usize,
+    pub points: usize,
+    pub point_pctg: f64,
+    pub wins: usize,
+    pub losses: usize,
+    pub ot_losses: usize,
+    /// "W", "L" or "OT"; missing before a team's first game.
+    pub streak_code: Option<String>,
+    pub streak_count: Option<usize>,
+}
+
+impl TeamStanding {
+    /// W-L-OTL and the current streak, e.g. "19-54-9 L3".
+    pub fn record(&self) -> String {
+        let record = format!("{}-{}-{}", self.wins, self.losses, self.ot_losses);
+        match (&self.streak_code, self.streak_count) {
+            (Some(streak_code), Some(streak_count)) if streak_count > 0 => {
+                format!("{} {}{}", record, streak_code, streak_count)
+            }
+            _ => record,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Standings {
+    standings: Vec<TeamStanding>,
+}
+
+/// The standings row for the team with `abbreviation`.
+pub fn team_standing(
+    standings_response_string: &str,
+    abbreviation: &str,
+) -> Result<TeamStanding, EngineError> {
+    let standings: Standings = from_json("standings", standings_response_string)?;
+    standings
+        .standings
+        .into_iter()
+        .find(|standing| standing.team_abbrev.default == abbreviation)
+        .ok_or_else(|| EngineError::data_shape("standings", format!("no {}", abbreviation)))
+}
+
+/// Both teams' records for the line under the game time, ours first, e.g.
+/// "SJS 19-54-9 L3 | MIN 39-34-9 L4". `None` if either team isn't in the
+/// standings, as happens before a season starts.
+pub fn records_line(
+    standings_response_string: &str,
+    ours: &str,
+    theirs: &str,
+) -> Result<Option<String>, EngineError> {
+    let standings: Standings = from_json("standings", standings_response_string)?;
+    let record = |abbreviation: &str| {
+        standings
+            .standings
+            .iter()
+            .find(|standing| standing.team_abbrev.default == abbreviation)
+            .map(|standing| format!("{} {}", abbreviation, standing.record()))
+    };
+    Ok(match (record(ours), record(theirs)) {
+        (Some(ours), Some(theirs)) => Some(format!("{} | {}", ours, theirs)),
+        _ => None,
+    })
+}
+
+fn ordinal(number: usize) -> String {
+    let suffix = match (number % 10, number % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", number, suffix)
+}
+
+/// Points percentage the way it's printed in the paper, e.g. ".287".
+fn format_point_pctg(point_pctg: f64) -> String {
+    let formatted = format!("{:.3}", point_pctg);
+    formatted
+        .strip_prefix('0')
+        .map(str::to_string)
+        .unwrap_or(formatted)
+}
+
+impl NextUp {
+    pub fn new_standings(
+        nickname: &str,
+        abbreviation: &str,
+        standings_response_string: &str,
+        utc_now: &DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Self, EngineError> {
+        let standing = team_standing(standings_response_string, abbreviation)?;
+        Ok(Self {
+            top: format!("{} Standings", nickname),
+            middle: format!(
+                "{} in {}",
+                ordinal(standing.division_sequence),
+                standing.division_name
+            ),
+            bottom: format!(
+                "{} pts  {} GP  {}",
+                standing.points,
+                standing.games_played,
+                format_point_pctg(standing.point_pctg)
+            ),
+            state: NextUpState::Standings,
+            source: Some(SourceKind::Standings),
+            ..Self::empty(utc_now, tz)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEFAULT_TIME_ZONE;
+
+    const STANDINGS_TEXT: &str = include_str!("../data/web_standings.json");
+
+    #[test]
+    fn test_ordinal() {
+        let ordinals: Vec<String> = [1, 2, 3, 4, 11, 12, 13, 21, 22, 32]
+            .iter()
+            .map(|number| ordinal(*number))
+            .collect();
+        assert_eq!(
+            ordinals,
+            ["1st", "2nd", "3rd", "4th", "11th", "12th", "13th", "21st", "22nd", "32nd"]
+        );
+    }
+
+    #[test]
+    fn test_format_point_pctg() {
+        assert_eq!(format_point_pctg(0.286585), ".287");
+        assert_eq!(format_point_pctg(0.695122), ".695");
+        assert_eq!(format_point_pctg(1.0), "1.000");
+    }
+
+    #[test]
+    fn test_standings() {
+        let now = DateTime::parse_from_rfc3339("2024-04-18T17:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let next_up =
+            NextUp::new_standings("Sharks", "SJS", STANDINGS_TEXT, &now, DEFAULT_TIME_ZONE)
+                .expect("standings");
+        assert_eq!(next_up.top, "Sharks Standings");
+        assert_eq!(next_up.middle, "8th in Pacific");
+        assert_eq!(next_up.bottom, "47 pts  82 GP  .287");
+        assert_eq!(next_up.state, NextUpState::Standings);
+
+        let next_up =
+            NextUp::new_standings("Rangers", "NYR", STANDINGS_TEXT, &now, DEFAULT_TIME_ZONE)
+                .expect("standings");
+        assert_eq!(next_up.middle, "1st in Metropolitan");
+        assert_eq!(next_up.bottom, "114 pts  82 GP  .695");
+    }
+
+    #[test]
+    fn test_records_line() {
+        assert_eq!(
+            records_line(STANDINGS_TEXT, "SJS", "MIN")
+                .unwrap()
+                .as_deref(),
+            Some("SJS 19-54-9 L3 | MIN 39-34-9 L4")
+        );
+        assert_eq!(
+            records_line(STANDINGS_TEXT, "LAK", "CAR")
+                .unwrap()
+                .as_deref(),
+            Some("LAK 44-27-11 OT1 | CAR 52-23-7 W4")
+        );
+        assert_eq!(records_line(STANDINGS_TEXT, "UTA", "SJS").unwrap(), None);
+    }
+
+    #[test]
+    fn test_record_without_streak() {
+        let standing = TeamStanding {
+            streak_code: None,
+            streak_count: None,
+            ..team_standing(STANDINGS_TEXT, "SJS").unwrap()
+        };
+        assert_eq!(standing.record(), "19-54-9");
+    }
+
+    #[test]
+    fn test_missing_team() {
+        assert!(matches!(
+            team_standing(STANDINGS_TEXT, "UTA"),
+            Err(EngineError::DataShape {
+                what: "standings",
+                ..
+            })
+        ));
+        assert!(matches!(
+            team_standing(&STANDINGS_TEXT[..STANDINGS_TEXT.len() / 2], "SJS"),
+            Err(EngineError::Json { .. })
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+//! What every handler shares: the configuration and flags parsed at startup,
+//! the clock, one HTTP client, upstream health and the caches built up
+//! between requests. Tests build their own, with whatever configuration they
+//! need.
+
+use crate::{
+    ahl::ScheduleCache,
+    cache::ResponseCache,
+    clock::{Clock, FixedClock, SystemClock},
+    config::Config,
+    goals::GoalTracker,
+    health::Health,
+    last_good::LastGood,
+    replay::{Recorder, Replay, ReplayClock},
+    stream::Streams,
+    Opt,
+};
+use anyhow::Result;
+use log::info;
+use std::{convert::TryInto, sync::Arc, time::Duration};
+
+/// Long enough for the slowest upstream page, short enough that a device
+/// isn't left waiting past its own timeout.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub opt: Arc<Opt>,
+    /// The system clock, `--now` or the replay's simulated clock.
+    pub clock: Arc<dyn Clock>,
+    /// Whether a request's `?now=` may override the clock: in debug builds,
+    /// and in tests whatever the build.
+    pub now_param: bool,
+    /// Shared so connections to upstreams are kept alive between requests.
+    pub client: surf::Client,
+    pub recorder: Option<Arc<Recorder>>,
+    pub replay: Option<Arc<Replay>>,
+    pub health: Arc<Health>,
+    pub responses: Arc<ResponseCache>,
+    /// Each route's last good response, served when upstream fails.
+    pub last_good: Arc<LastGood>,
+    pub barracuda_schedule: Arc<ScheduleCache>,
+    pub goals: Arc<GoalTracker>,
+    pub streams: Arc<Streams>,
+}
+
+impl AppState {
+    /// State for `config`, recording or replaying upstream traffic and
+    /// fixing the clock as the flags in `opt` ask.
+    pub fn new(config: Config, opt: Opt) -> Result<Self> {
+        let client = surf::Config::new()
+            .set_http_keep_alive(true)
+            .set_timeout(Some(UPSTREAM_TIMEOUT))
+            .try_into()?;
+        let recorder = match opt.record.as_ref() {
+            Some(dir) => {
+                info!("recording upstream responses to {}", dir.display());
+                Some(Arc::new(Recorder::new(dir)?))
+            }
+            None => None,
+        };
+        let replay = match opt.replay.as_ref() {
+            Some(dir) => {
+                info!("replaying upstream responses from {}", dir.display());
+                Some(Arc::new(Replay::load(dir)?))
+            }
+            None => None,
+        };
+        let clock: Arc<dyn Clock> = match (opt.now, &replay) {
+            (Some(now), _) => {
+                info!("pretending it is {}", now);
+                Arc::new(FixedClock(now))
+            }
+            (None, Some(replay)) => Arc::new(ReplayClock(replay.clone())),
+            (None, None) => Arc::new(SystemClock),
+        };
+        // Devices poll every `pregame_secs` during a game, so each sees a
+        // goal alert once.
+        let goals = GoalTracker::new(chrono::Duration::seconds(config.sleep.pregame_secs));
+        let last_good = LastGood::new(
+            &config.state_dir,
+            chrono::Duration::hours(config.max_stale_hours),
+        );
+        Ok(Self {
+            goals: Arc::new(goals),
+            last_good: Arc::new(last_good),
+            config: Arc::new(config),
+            opt: Arc::new(opt),
+            clock,
+            now_param: cfg!(debug_assertions),
+            client,
+            recorder,
+            replay,
+            health: Arc::default(),
+            responses: Arc::default(),
+            barracuda_schedule: Arc::default(),
+            streams: Arc::default(),
+        })
+    }
+
+    /// Whether upstream responses come from a recording.
+    pub fn replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// The default configuration and no flags.
+    #[cfg(test)]
+    pub fn test() -> Self {
+        use structopt::StructOpt;
+        let mut state =
+            Self::new(Config::default(), Opt::from_iter(&["magtag_gateway"])).expect("test state");
+        state.now_param = true;
+        state
+    }
+}
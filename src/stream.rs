@@ -0,0 +1,246 @@
+//! Shared pollers behind `/stream/:team`. Each team and time zone being
+//! watched gets one background task that polls upstream and hands every
+//! changed `NextUp` to all of its subscribers, so upstream traffic doesn't
+//! grow with the number of listeners.
+
+use crate::{
+    fetch_nhl_next_up, goals::GoalTracker, remember, state::AppState, NextUp, NextUpState,
+    TEAM_NICKNAMES,
+};
+use async_std::{
+    channel::{self, Receiver, Sender},
+    task,
+};
+use chrono_tz::Tz;
+use log::{info, warn};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/// Matches the cache lifetime of a live game's responses; polling faster
+/// would only see the same data.
+const LIVE_POLL: Duration = Duration::from_secs(15);
+const MAX_POLL: Duration = Duration::from_secs(5 * 60);
+/// How often a quiet stream sends a `keep_alive` event. Writing is the only
+/// way to notice a client has gone, which lets an idle poller stop.
+pub const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Updates held for a subscriber before it is dropped as too slow; it can
+/// reconnect and start again from the latest.
+const SUBSCRIBER_BUFFER: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamKey {
+    pub team_id: usize,
+    pub tz: Tz,
+}
+
+#[derive(Default)]
+struct Stream {
+    subscribers: Vec<Sender<NextUp>>,
+    last: Option<NextUp>,
+}
+
+/// What subscribers would see, leaving out the clock, the poll hint and the
+/// date, which for some states is just the time of the poll. A game that
+/// moves changes its text as well.
+fn display_key(next: &NextUp) -> serde_json::Value {
+    let mut value = serde_json::to_value(next).unwrap_or_default();
+    if let Some(fields) = value.as_object_mut() {
+        for field in &["time", "sleep", "date"] {
+            fields.remove(*field);
+        }
+    }
+    value
+}
+
+fn poll_interval(next: &NextUp) -> Duration {
+    match next.state {
+        NextUpState::Live => LIVE_POLL,
+        _ => Duration::from_secs(next.sleep.max(0) as u64).clamp(LIVE_POLL, MAX_POLL),
+    }
+}
+
+#[derive(Default)]
+pub struct Streams {
+    streams: Mutex<HashMap<StreamKey, Stream>>,
+}
+
+impl Streams {
+    /// Add a subscriber, sending it the latest `NextUp` if there is one.
+    /// Returns whether it is the first, in which case a poller is needed.
+    fn add_subscriber(&self, key: StreamKey) -> (Receiver<NextUp>, bool) {
+        let (sender, receiver) = channel::bounded(SUBSCRIBER_BUFFER);
+        let mut streams = self.streams.lock().expect("streams");
+        let stream = streams.entry(key).or_default();
+        if let Some(last) = &stream.last {
+            let _ = sender.try_send(last.clone());
+        }
+        let first = stream.subscribers.is_empty();
+        stream.subscribers.push(sender);
+        (receiver, first)
+    }
+
+    /// Send `next` to every subscriber if it differs from the last one sent,
+    /// dropping any that have gone or fallen too far behind.
+    fn publish(&self, key: StreamKey, next: NextUp) {
+        let mut streams = self.streams.lock().expect("streams");
+        let stream = streams.entry(key).or_default();
+        let changed = match &stream.last {
+            Some(last) => display_key(last) != display_key(&next),
+            None => true,
+        };
+        if changed {
+            stream
+                .subscribers
+                .retain(|subscriber| subscriber.try_send(next.clone()).is_ok());
+            stream.last = Some(next);
+        }
+    }
+
+    /// Drop subscribers that have gone away, and the whole stream once none
+    /// are left, in which case its poller should stop.
+    fn keep_polling(&self, key: StreamKey) -> bool {
+        let mut streams = self.streams.lock().expect("streams");
+        let stream = streams.entry(key).or_default();
+        stream
+            .subscribers
+            .retain(|subscriber| !subscriber.is_closed());
+        if stream.subscribers.is_empty() {
+            streams.remove(&key);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+/// Subscribe to changes for `key`, starting its poller if it is the first
+/// subscriber.
+pub fn subscribe(state: &AppState, key: StreamKey) -> Receiver<NextUp> {
+    let (receiver, first) = state.streams.add_subscriber(key);
+    if first {
+        task::spawn(poll(state.clone(), key));
+    }
+    receiver
+}
+
+/// Polls until the last subscriber leaves. Goals are tracked per stream so
+/// the poller doesn't use up alerts meant for polling devices.
+async fn poll(state: AppState, key: StreamKey) {
+    info!("streaming team {} in {}", key.team_id, key.tz);
+    let goals = GoalTracker::default();
+    while state.streams.keep_polling(key) {
+        let now = state.clock.now();
+        let next = match fetch_nhl_next_up(&state, key.team_id, &now, key.tz).await {
+            Ok(next) => goals.observe(key.team_id, next, &now),
+            Err(err) => {
+                warn!("stream for team {} unavailable: {:#}", key.team_id, err);
+                let nickname = TEAM_NICKNAMES.get(&key.team_id).unwrap_or(&"Unknown");
+                NextUp::unavailable(
+                    &format!("{} Next Up", nickname),
+                    &format!("{:#}", err),
+                    &state.config.sleep,
+                    &now,
+                    key.tz,
+                )
+            }
+        };
+        // Shared with `/next/:team`, so a failing upstream shows the last
+        // good screen, marked stale, the same way on both.
+        let next = remember(&state, &format!("next/{}", key.team_id), next, &now, key.tz)
+            .with_sleep(&state.config.sleep, &now);
+        let interval = poll_interval(&next);
+        state.streams.publish(key, next);
+        task::sleep(interval).await;
+    }
+    info!("stopped streaming team {} in {}", key.team_id, key.tz);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEFAULT_TIME_ZONE;
+
+    const KEY: StreamKey = StreamKey {
+        team_id: 28,
+        tz: DEFAULT_TIME_ZONE,
+    };
+
+    fn next_up(bottom: &str, time: &str) -> NextUp {
+        NextUp {
+            top: "2nd | 18:32".to_string(),
+            bottom: bottom.to_string(),
+            time: time.to_string(),
+            state: NextUpState::Live,
+            ..NextUp::default()
+        }
+    }
+
+    #[test]
+    fn test_publish_only_changes() {
+        let streams = Streams::default();
+        let (first, is_first) = streams.add_subscriber(KEY);
+        assert!(is_first);
+        let (second, is_first) = streams.add_subscriber(KEY);
+        assert!(!is_first);
+
+        streams.publish(KEY, next_up("SJS 0 MIN 0", "7:41PM"));
+        // Only the clock moved.
+        streams.publish(KEY, next_up("SJS 0 MIN 0", "7:42PM"));
+        streams.publish(KEY, next_up("SJS 1 MIN 0", "7:42PM"));
+
+        for receiver in &[first, second] {
+            assert_eq!(receiver.try_recv().unwrap().bottom, "SJS 0 MIN 0");
+            assert_eq!(receiver.try_recv().unwrap().bottom, "SJS 1 MIN 0");
+            assert!(receiver.try_recv().is_err());
+        }
+    }
+
+    #[test]
+    fn test_late_subscriber_gets_latest() {
+        let streams = Streams::default();
+        let _first = streams.add_subscriber(KEY);
+        streams.publish(KEY, next_up("SJS 1 MIN 0", "7:42PM"));
+        let (late, is_first) = streams.add_subscriber(KEY);
+        assert!(!is_first);
+        assert_eq!(late.try_recv().unwrap().bottom, "SJS 1 MIN 0");
+    }
+
+    #[test]
+    fn test_stops_without_subscribers() {
+        let streams = Streams::default();
+        let (receiver, _) = streams.add_subscriber(KEY);
+        assert!(streams.keep_polling(KEY));
+        drop(receiver);
+        assert!(!streams.keep_polling(KEY));
+        // The next subscriber starts over with a new poller.
+        let (_receiver, is_first) = streams.add_subscriber(KEY);
+        assert!(is_first);
+    }
+
+    #[test]
+    fn test_drops_slow_subscribers() {
+        let streams = Streams::default();
+        let (slow, _) = streams.add_subscriber(KEY);
+        for goals in 0..=SUBSCRIBER_BUFFER {
+            streams.publish(KEY, next_up(&format!("SJS {} MIN 0", goals), "7:42PM"));
+        }
+        // What was buffered is still delivered, then the stream ends.
+        for _ in 0..SUBSCRIBER_BUFFER {
+            assert!(slow.try_recv().is_ok());
+        }
+        assert!(slow.is_closed());
+        assert!(!streams.keep_polling(KEY));
+    }
+
+    #[test]
+    fn test_poll_interval() {
+        assert_eq!(poll_interval(&next_up("", "")), LIVE_POLL);
+        let upcoming = |sleep| NextUp {
+            sleep,
+            state: NextUpState::Upcoming,
+            ..NextUp::default()
+        };
+        assert_eq!(poll_interval(&upcoming(60)), Duration::from_secs(60));
+        assert_eq!(poll_interval(&upcoming(7200)), MAX_POLL);
+        assert_eq!(poll_interval(&upcoming(1)), LIVE_POLL);
+    }
+}
@@ -0,0 +1,221 @@
+//! Games we hold tickets for, loaded from a TOML list so adding a ticket
+//! doesn't need a rebuild.
+
+use crate::{load_text, state::AppState, TEAM_ABBREVIATIONS};
+use anyhow::{bail, Context, Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum League {
+    Ahl,
+    Nhl,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Ticket {
+    pub opponent: String,
+    pub date: DateTime<Utc>,
+    pub league: League,
+    pub team: String,
+    pub section: Option<String>,
+    pub row: Option<String>,
+    pub seat: Option<String>,
+    pub notes: Option<String>,
+}
+
+impl Ticket {
+    pub fn is_for(&self, league: League, team: &str) -> bool {
+        self.league == league && self.team.eq_ignore_ascii_case(team)
+    }
+
+    /// Where we're sitting, e.g. `Sec 113 Row F Seat 7`, if the ticket says.
+    pub fn seat_description(&self) -> Option<String> {
+        let parts: Vec<String> = [
+            ("Sec", &self.section),
+            ("Row", &self.row),
+            ("Seat", &self.seat),
+        ]
+        .iter()
+        .filter_map(|(label, value)| value.as_ref().map(|value| format!("{} {}", label, value)))
+        .collect();
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(" "))
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.opponent.trim().is_empty() {
+            bail!("opponent is empty");
+        }
+        if self.team.trim().is_empty() {
+            bail!("team is empty");
+        }
+        if self.league == League::Nhl
+            && !TEAM_ABBREVIATIONS
+                .values()
+                .any(|abbrev| abbrev.eq_ignore_ascii_case(&self.team))
+        {
+            bail!("unknown NHL team {}", self.team);
+        }
+        if self.row.is_some() && self.section.is_none() {
+            bail!("row given without a section");
+        }
+        if self.seat.is_some() && self.row.is_none() {
+            bail!("seat given without a row");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TicketList {
+    #[serde(default)]
+    tickets: Vec<Ticket>,
+}
+
+/// Parse and validate a ticket list, sorted by date.
+pub fn parse_tickets(tickets_text: &str) -> Result<Vec<Ticket>> {
+    let list: TicketList = toml::from_str(tickets_text).context("ticket list")?;
+    let mut tickets = list.tickets;
+    for (index, ticket) in tickets.iter().enumerate() {
+        ticket.validate().with_context(|| {
+            format!(
+                "ticket {} ({} on {})",
+                index + 1,
+                ticket.opponent,
+                ticket.date
+            )
+        })?;
+    }
+    tickets.sort_by_key(|ticket| ticket.date);
+    for pair in tickets.windows(2) {
+        if pair[0].date == pair[1].date && pair[0].is_for(pair[1].league, &pair[1].team) {
+            bail!("duplicate tickets for {} on {}", pair[1].team, pair[1].date);
+        }
+    }
+    Ok(tickets)
+}
+
+/// Load the ticket list from a local path or an http(s) URL.
+pub async fn load_tickets(state: &AppState, source: &str) -> Result<Vec<Ticket>, Error> {
+    let tickets_text = load_text(state, "tickets", source).await?;
+    parse_tickets(&tickets_text).with_context(|| format!("loading tickets from {}", source))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TICKETS_TEXT: &str = include_str!("../data/tickets.toml");
+
+    fn error_for(tickets_text: &str) -> String {
+        format!("{:#}", parse_tickets(tickets_text).unwrap_err())
+    }
+
+    #[test]
+    fn test_parse_tickets() {
+        let tickets = parse_tickets(TICKETS_TEXT).expect("tickets");
+        assert_eq!(tickets.len(), 21);
+        assert_eq!(tickets[0].opponent, "Icehogs");
+        assert!(tickets
+            .iter()
+            .all(|ticket| ticket.is_for(League::Ahl, "sjb")));
+        assert!(tickets.windows(2).all(|pair| pair[0].date < pair[1].date));
+    }
+
+    #[test]
+    fn test_seat_description() {
+        let tickets = parse_tickets(
+            r#"
+            [[tickets]]
+            opponent = "Wild"
+            date = "2024-03-30T02:30:00Z"
+            league = "nhl"
+            team = "SJS"
+            section = "113"
+            row = "F"
+            seat = "7"
+            notes = "Parking pass in the glovebox"
+
+            [[tickets]]
+            opponent = "Reign"
+            date = "2024-03-27T02:00:00Z"
+            league = "ahl"
+            team = "SJB"
+            "#,
+        )
+        .expect("tickets");
+        assert_eq!(tickets[0].seat_description(), None);
+        assert_eq!(
+            tickets[1].seat_description().as_deref(),
+            Some("Sec 113 Row F Seat 7")
+        );
+        assert!(tickets[1].is_for(League::Nhl, "SJS"));
+    }
+
+    #[test]
+    fn test_invalid_tickets() {
+        assert!(error_for(
+            r#"
+            [[tickets]]
+            opponent = " "
+            date = "2024-03-27T02:00:00Z"
+            league = "ahl"
+            team = "SJB"
+            "#
+        )
+        .contains("opponent is empty"));
+        assert!(error_for(
+            r#"
+            [[tickets]]
+            opponent = "Wild"
+            date = "2024-03-27T02:00:00Z"
+            league = "nhl"
+            team = "SJB"
+            "#
+        )
+        .contains("unknown NHL team SJB"));
+        assert!(error_for(
+            r#"
+            [[tickets]]
+            opponent = "Reign"
+            date = "2024-03-27T02:00:00Z"
+            league = "ahl"
+            team = "SJB"
+            row = "F"
+            "#
+        )
+        .contains("row given without a section"));
+        assert!(error_for(
+            r#"
+            [[tickets]]
+            opponent = "Reign"
+            date = "2024-03-27T02:00:00Z"
+            league = "ahl"
+            team = "SJB"
+
+            [[tickets]]
+            opponent = "Reign"
+            date = "2024-03-27T02:00:00Z"
+            league = "ahl"
+            team = "sjb"
+            "#
+        )
+        .contains("duplicate tickets"));
+        assert!(error_for(
+            r#"
+            [[tickets]]
+            opponent = "Reign"
+            date = "next tuesday"
+            league = "ahl"
+            team = "SJB"
+            "#
+        )
+        .contains("ticket list"));
+    }
+}